@@ -5,6 +5,8 @@ fn stage1(sh: &mut boxxy::Shell, _args: Vec<String>) -> Result<(), boxxy::Error>
     shprintln!(sh, "[*] starting stage1");
     acme_redirect::sandbox::init(&DaemonArgs {
         bind_addr: Some("[::]:80".to_string()),
+        tls_bind_addr: None,
+        metrics_bind_addr: None,
         chroot: true,
         user: Some("nobody".to_string()),
     })
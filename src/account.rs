@@ -0,0 +1,130 @@
+use crate::acme;
+use crate::args::{AccountAction, AccountArgs};
+use crate::client::{Account, Directory, SigningKey};
+use crate::config::{self, Config};
+use crate::errors::*;
+use crate::persist::FilePersist;
+use std::fs;
+
+fn contact(emails: &[String]) -> Vec<String> {
+    emails
+        .iter()
+        .map(|email| format!("mailto:{email}"))
+        .collect()
+}
+
+fn load<'a>(
+    dir: &'a Directory,
+    persist: &FilePersist,
+    acme_url: &str,
+    account: &str,
+) -> Result<Account<'a>> {
+    let key = persist
+        .load_acc_privkey(acme_url, account)?
+        .ok_or_else(|| anyhow!("No account key found for {:?}", account))?;
+    dir.find_account(&key)
+        .context("Failed to look up account at acme server")
+}
+
+fn show(acme_url: &str, account: &str, acc: &Account) {
+    println!("account:  {account}");
+    println!("acme_url: {acme_url}");
+    println!("url:      {}", acc.url());
+    if let Some(status) = &acc.api.status {
+        println!("status:   {status}");
+    }
+    for contact in &acc.api.contact {
+        println!("contact:  {contact}");
+    }
+}
+
+pub fn run(config: Config, args: AccountArgs) -> Result<()> {
+    if !config::valid_account_name(&args.account) {
+        bail!("Account name is not allowed: {:?}", args.account);
+    }
+
+    let persist = FilePersist::new(&config);
+    persist
+        .migrate_acc_privkey(&config.acme.acme_url)
+        .context("Failed to migrate account key")?;
+
+    let acme_url = args.acme_url.as_deref().unwrap_or(&config.acme.acme_url);
+    let dir = Directory::from_url(acme_url)?;
+
+    match args.action {
+        AccountAction::Show => {
+            let acc = load(&dir, &persist, acme_url, &args.account)?;
+            show(acme_url, &args.account, &acc);
+        }
+        AccountAction::Register => {
+            if persist.load_acc_privkey(acme_url, &args.account)?.is_some() {
+                bail!("Account {:?} already exists", args.account);
+            }
+
+            let eab = if acme_url == config.acme.acme_url {
+                acme::load_eab(&config.acme).context("Invalid external account binding")?
+            } else {
+                None
+            };
+            let emails = config.acme.acme_email.iter().cloned().collect::<Vec<_>>();
+            info!("registering account {:?}", args.account);
+            let acc = dir.register_account(contact(&emails), eab.as_ref())?;
+            persist.store_acc_privkey(acme_url, &args.account, &acc.key().to_pem()?)?;
+            show(acme_url, &args.account, &acc);
+        }
+        AccountAction::UpdateContact(update) => {
+            let emails = if update.emails.is_empty() {
+                config.acme.acme_email.iter().cloned().collect()
+            } else {
+                update.emails
+            };
+
+            let mut acc = load(&dir, &persist, acme_url, &args.account)?;
+            info!("updating contact of account {:?}", args.account);
+            acc.update_contact(contact(&emails))?;
+            show(acme_url, &args.account, &acc);
+        }
+        AccountAction::KeyRollover => {
+            let mut acc = load(&dir, &persist, acme_url, &args.account)?;
+            let old_key = acc.key().to_pem()?;
+            let new_key = SigningKey::generate()?;
+            // the new key needs to be on disk before the acme server switches to it
+            let staged = persist
+                .stage_acc_privkey(acme_url, &args.account, &new_key.to_pem()?)
+                .context("Failed to store new account key")?;
+
+            info!("replacing key of account {:?}", args.account);
+            if let Err(err) = acc.key_rollover(new_key) {
+                if dir.find_account(&old_key).is_ok() {
+                    fs::remove_file(&staged).ok();
+                } else {
+                    warn!(
+                        "The old account key doesn't work anymore, keeping the new key at {:?}",
+                        staged
+                    );
+                }
+                return Err(err.context("Failed to replace account key"));
+            }
+
+            persist
+                .commit_acc_privkey(acme_url, &args.account, &staged)
+                .with_context(|| {
+                    anyhow!("Account key was replaced but is still at {:?}", staged)
+                })?;
+            info!("successfully replaced account key");
+        }
+        AccountAction::Deactivate(deactivate) => {
+            if !deactivate.yes {
+                bail!("Deactivating an account can't be undone, pass --yes to confirm");
+            }
+
+            let mut acc = load(&dir, &persist, acme_url, &args.account)?;
+            info!("deactivating account {:?}", args.account);
+            acc.deactivate()?;
+            persist.remove_acc_privkey(acme_url, &args.account)?;
+            show(acme_url, &args.account, &acc);
+        }
+    }
+
+    Ok(())
+}
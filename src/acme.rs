@@ -1,21 +1,69 @@
-use crate::chall::Challenge;
+use crate::cert::Certificate;
+use crate::chall::ChallengeSolver;
+use crate::client::{self, ApiIdentifier, ApiNewOrder, Directory, ExternalAccountBinding};
+use crate::config::{self, AcmeConfig, ChallengeType, KeyType};
+use crate::csr;
 use crate::errors::*;
 use crate::persist::FilePersist;
-use acme_micro::create_p384_key;
-use acme_micro::{Directory, DirectoryUrl};
-use std::time::Duration;
+use openssl::pkey::PKey;
+use openssl::sha::sha256;
+use std::fs;
+use std::thread;
 
 #[derive(Debug)]
 pub struct Request<'a> {
     pub acme_url: &'a str,
+    pub account: &'a str,
     pub account_email: Option<&'a str>,
     pub primary_name: &'a str,
     pub alt_names: &'a [String],
+    pub must_staple: bool,
+    pub key_type: KeyType,
+    pub folder: Option<&'a str>,
+    pub private_key: Option<&'a str>,
+    pub csr: Option<&'a [u8]>,
+    pub eab: Option<&'a ExternalAccountBinding>,
+    /// The ari identifier of the certificate that is replaced
+    pub replaces: Option<&'a str>,
+    pub profile: Option<&'a str>,
 }
 
-pub fn request(persist: FilePersist, challenge: &mut Challenge, req: &Request) -> Result<()> {
-    let url = DirectoryUrl::Other(req.acme_url);
-    let dir = Directory::from_url(url)?;
+impl Request<'_> {
+    /// The names of the certificate, the primary name always comes first
+    fn names(&self) -> Vec<&str> {
+        config::cert_names(self.primary_name, self.alt_names)
+    }
+}
+
+/// Load the external account binding credentials, if configured
+pub fn load_eab(config: &AcmeConfig) -> Result<Option<ExternalAccountBinding>> {
+    let hmac_key = match (&config.eab_hmac_key, &config.eab_hmac_key_file) {
+        (Some(_), Some(_)) => bail!("eab_hmac_key and eab_hmac_key_file can't be used together"),
+        (Some(key), None) => Some(key.clone()),
+        (None, Some(path)) => Some(
+            fs::read_to_string(path)
+                .with_context(|| anyhow!("Failed to read eab hmac key from {:?}", path))?,
+        ),
+        (None, None) => None,
+    };
+
+    match (&config.eab_kid, hmac_key) {
+        (Some(kid), Some(hmac_key)) => {
+            let eab = ExternalAccountBinding::new(kid.clone(), &hmac_key)?;
+            Ok(Some(eab))
+        }
+        (None, None) => Ok(None),
+        (Some(_), None) => bail!("eab_kid is configured without an eab hmac key"),
+        (None, Some(_)) => bail!("eab hmac key is configured without eab_kid"),
+    }
+}
+
+pub fn request(
+    persist: FilePersist,
+    solver: &mut dyn ChallengeSolver,
+    req: &Request,
+) -> Result<()> {
+    let dir = Directory::from_url(req.acme_url)?;
 
     let contact = if let Some(email) = req.account_email {
         vec![format!("mailto:{email}")]
@@ -23,36 +71,82 @@ pub fn request(persist: FilePersist, challenge: &mut Challenge, req: &Request) -
         vec![]
     };
 
-    let acc = if let Some(acc) = persist.load_acc_privkey()? {
-        info!("authenticating with existing account");
+    let acc = if let Some(acc) = persist.load_acc_privkey(req.acme_url, req.account)? {
+        info!("authenticating with existing account {:?}", req.account);
         dir.load_account(&acc, contact)?
     } else {
-        info!("registering account");
-        let acc = dir.register_account(contact)?;
+        info!("registering account {:?}", req.account);
+        let acc = dir.register_account(contact, req.eab)?;
         info!("successfully created account, saving private key");
-        persist.store_acc_privkey(&acc.acme_private_key_pem()?)?;
+        persist.store_acc_privkey(req.acme_url, req.account, &acc.key().to_pem()?)?;
         acc
     };
 
     // Order a new TLS certificate for a domain.
-    let alt_names = req.alt_names.iter().map(AsRef::as_ref).collect::<Vec<_>>();
+    let names = req.names();
     info!("sending certificate order");
-    let mut ord_new = acc.new_order(req.primary_name, &alt_names)?;
+    let identifiers = names
+        .iter()
+        .map(|name| match config::parse_ip(name) {
+            Some(addr) => ApiIdentifier::ip(&addr),
+            None => ApiIdentifier::dns(name),
+        })
+        .collect::<Vec<_>>();
+    if let Some(profile) = req.profile {
+        let profiles = &dir.api().meta.profiles;
+        if !profiles.contains_key(profile) {
+            bail!(
+                "Acme server doesn't offer profile {:?}, available profiles: {:?}",
+                profile,
+                profiles.keys().collect::<Vec<_>>()
+            );
+        }
+    }
+    let profile = req.profile.map(String::from);
+
+    let replaces = req.replaces.filter(|_| dir.api().renewal_info.is_some());
+    let mut order = match acc.new_order(&ApiNewOrder {
+        identifiers: identifiers.clone(),
+        replaces: replaces.map(String::from),
+        profile: profile.clone(),
+    }) {
+        Ok(order) => order,
+        Err(err) if replaces.is_some() => {
+            warn!(
+                "Failed to order certificate as replacement, retrying without: {:#}",
+                err
+            );
+            acc.new_order(&ApiNewOrder {
+                identifiers,
+                replaces: None,
+                profile,
+            })?
+        }
+        Err(err) => return Err(err),
+    };
 
     // If the ownership of the domain(s) have already been
     // authorized in a previous order, you might be able to
     // skip validation. The ACME API provider decides.
-    let ord_csr = loop {
+    let mut attempts = 0;
+    loop {
         // are we done?
-        if let Some(ord_csr) = ord_new.confirm_validations() {
+        if order.is_ready() {
             info!("order has been confirmed");
-            break ord_csr;
+            break;
+        }
+        attempts += 1;
+        if attempts > client::POLL_RETRIES {
+            bail!(
+                "order is still {:?} after {} attempts",
+                order.api.status,
+                attempts
+            );
         }
 
         // Get the possible authorizations (for a single domain
         // this will only be one element).
         info!("fetch necessary authentications");
-        let auths = ord_new.authorizations()?;
 
         // For HTTP, the challenge is a text file that needs to
         // be placed in your web server's root:
@@ -64,55 +158,156 @@ pub fn request(persist: FilePersist, challenge: &mut Challenge, req: &Request) -
         // certificate for:
         //
         // http://mydomain.io/.well-known/acme-challenge/<token>
-        for auth in &auths {
-            let chall = auth
-                .http_challenge()
-                .ok_or_else(|| anyhow!("acme server didn't offer http challenge"))?;
+        //
+        // For DNS, the proof needs to be published as TXT record:
+        //
+        // _acme-challenge.mydomain.io
+        //
+        // For TLS-ALPN, the proof is presented by the daemon in a
+        // self-signed certificate on port 443.
+        for auth_url in &order.api.authorizations {
+            let auth = acc.authorization(auth_url)?;
+            let domain = &auth.identifier.value;
+            match auth.status.as_str() {
+                "pending" => (),
+                "valid" => {
+                    debug!("authorization for {:?} is already valid", domain);
+                    continue;
+                }
+                status => bail!("authorization for {:?} is {}", domain, status),
+            }
 
-            // The token is the filename.
-            let token = chall.http_token();
+            let challenge_type = solver.challenge_type();
+            let chall = auth.challenge(challenge_type.as_str()).ok_or_else(|| {
+                anyhow!(
+                    "acme server didn't offer {} challenge",
+                    challenge_type.as_str()
+                )
+            })?;
 
-            // The proof is the contents of the file
-            let proof = chall.http_proof()?;
+            let key_auth = acc.key_authorization(&chall.token)?;
+            let proof = match challenge_type {
+                // The proof is the contents of the file
+                ChallengeType::Http01 => key_auth,
+                // The proof is the content of the TXT record
+                ChallengeType::Dns01 => client::base64url(&sha256(key_auth.as_bytes())),
+                // The proof is embedded into the validation certificate
+                ChallengeType::TlsAlpn01 => sha256(key_auth.as_bytes())
+                    .iter()
+                    .map(|b| format!("{b:02x}"))
+                    .collect(),
+            };
 
             // Place the proof
-            challenge.write(token, &proof)?;
+            solver.present(domain, &chall.token, &proof)?;
 
-            // After the file is accessible from the web, the calls
-            // this to tell the ACME API to start checking the
+            // After the proof is accessible, we call validate
+            // to tell the ACME API to start checking the
             // existence of the proof.
             //
             // The order at ACME will change status to either
             // confirm ownership of the domain, or fail due to the
             // not finding the proof. To see the change, we poll
             // the API with 5000 milliseconds wait between.
-            chall.validate(Duration::from_millis(5000))?;
+            acc.validate(auth_url, chall)?;
         }
 
         // Update the state against the ACME API.
-        ord_new.refresh()?;
-    };
+        acc.refresh_order(&mut order)?;
+        if order.api.status == "invalid" {
+            bail!("order has become invalid");
+        }
+        if !order.is_ready() {
+            thread::sleep(client::POLL_DELAY);
+        }
+    }
 
-    // Ownership is proven. Create a private key for
-    // the certificate. These are provided for convenience, you
-    // can provide your own keypair instead if you want.
-    let pkey_pri = create_p384_key()?;
+    // Ownership is proven. Create a private key and csr for
+    // the certificate, unless a custom csr is used.
+    let (csr, private_key) = if let Some(csr) = req.csr {
+        info!("using custom csr");
+        (csr::load(csr)?, None)
+    } else {
+        // reuse the existing key, if configured
+        let (pkey, private_key) = if let Some(private_key) = req.private_key {
+            info!("reusing existing private key");
+            let pkey = PKey::private_key_from_pem(private_key.as_bytes())
+                .context("Failed to load existing private key")?;
+            (pkey, private_key.to_string())
+        } else {
+            let pkey = csr::create_private_key(req.key_type)?;
+            let private_key = String::from_utf8(pkey.private_key_to_pem_pkcs8()?)?;
+            (pkey, private_key)
+        };
+        let csr = csr::create(&pkey, &names, req.must_staple)?;
+        (csr, Some(private_key))
+    };
 
     // Submit the CSR. This causes the ACME provider to enter a
     // state of "processing" that must be polled until the
     // certificate is either issued or rejected. Again we poll
     // for the status change.
-    let ord_cert = ord_csr.finalize_pkey(pkey_pri, Duration::from_millis(5000))?;
+    acc.finalize(&mut order, &csr.to_der()?)?;
 
-    // Now download the certificate. Also stores the cert in
-    // the persistence.
+    // Now download the certificate.
     info!("downloading certificate");
-    let cert = ord_cert.download_cert()?;
+    let chain = acc.download_cert(&order)?;
+    let cert = if let Some(private_key) = private_key {
+        Certificate::new(req.key_type, private_key, chain)
+    } else {
+        let pkey = csr.public_key()?;
+        let key_type = csr::key_type(&pkey);
+        Certificate::without_key(key_type, chain)
+    };
 
     info!("storing certificate");
     persist
-        .store_cert(req.primary_name, &cert)
+        .store_cert(req.primary_name, req.folder, &cert)
         .context("Failed to store certificate")?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_names() {
+        let alt_names = vec!["example.com".to_string(), "www.example.com".to_string()];
+        let req = Request {
+            acme_url: "https://acme.example.com/directory",
+            account: "default",
+            account_email: None,
+            primary_name: "example.com",
+            alt_names: &alt_names,
+            must_staple: false,
+            key_type: KeyType::EcdsaP384,
+            folder: None,
+            private_key: None,
+            csr: None,
+            eab: None,
+            replaces: None,
+            profile: None,
+        };
+        assert_eq!(req.names(), &["example.com", "www.example.com"]);
+    }
+
+    #[test]
+    fn test_load_eab() {
+        let mut config = AcmeConfig::default();
+        assert_eq!(load_eab(&config).unwrap(), None);
+
+        config.eab_kid = Some("kid-1".to_string());
+        assert!(load_eab(&config).is_err());
+
+        config.eab_hmac_key = Some("c2VjcmV0".to_string());
+        assert_eq!(
+            load_eab(&config).unwrap(),
+            Some(ExternalAccountBinding {
+                kid: "kid-1".to_string(),
+                hmac_key: b"secret".to_vec(),
+            })
+        );
+    }
+}
@@ -1,7 +1,9 @@
+use crate::config::{DEFAULT_ACCOUNT, RenewThreshold};
 use crate::errors::*;
-use clap::{ArgAction, CommandFactory, Parser};
+use clap::{ArgAction, CommandFactory, Parser, Subcommand, ValueEnum};
 use clap_complete::Shell;
 use std::io::stdout;
+use std::path::PathBuf;
 
 #[derive(Debug, Parser)]
 #[command(version)]
@@ -52,11 +54,15 @@ pub enum Cmd {
     /// Run the redirect daemon
     Daemon(DaemonArgs),
     /// Show the status of our certificates
-    Status,
+    Status(StatusArgs),
     /// Request new certificates if needed
     Renew(RenewArgs),
     /// Check if the challenges could be completed
     Check(CheckArgs),
+    /// Manage the account at the acme server
+    Account(AccountArgs),
+    /// Revoke a certificate
+    Revoke(RevokeArgs),
     /// Load the configuration and dump it to stdout as json
     DumpConfig,
 }
@@ -66,6 +72,12 @@ pub struct DaemonArgs {
     /// The address to listen on
     #[arg(short = 'B', long, env = "ACME_BIND_ADDR")]
     pub bind_addr: Option<String>,
+    /// The address to answer tls-alpn-01 challenges on
+    #[arg(long, env = "ACME_TLS_BIND_ADDR")]
+    pub tls_bind_addr: Option<String>,
+    /// The address to serve prometheus metrics on
+    #[arg(long, env = "ACME_METRICS_BIND_ADDR")]
+    pub metrics_bind_addr: Option<String>,
     /// Drop from root to this user
     #[arg(long)]
     pub user: Option<String>,
@@ -82,7 +94,6 @@ pub struct RenewArgs {
     /// Renew certificates even if they are not about to expire
     #[arg(long)]
     pub force_renew: bool,
-    // TODO: add code to check if the cert actually fulfills the dns_names in the config
     /// Do not execute the configured exec commands
     #[arg(long)]
     pub skip_restarts: bool,
@@ -92,16 +103,121 @@ pub struct RenewArgs {
     /// Only execute hooks without actually renewing certs
     #[arg(long)]
     pub hooks_only: bool,
+    /// Generate a new private key even if reuse_key is configured
+    #[arg(long)]
+    pub new_key: bool,
+    /// Write a json report of the renewal outcomes to this file
+    #[arg(long, value_name = "path")]
+    pub report: Option<PathBuf>,
     /// Only renew specific certs
     pub certs: Vec<String>,
 }
 
+#[derive(Debug, Clone, Parser)]
+pub struct StatusArgs {
+    /// The output format
+    #[arg(long, value_enum, default_value_t = StatusFormat::Text)]
+    pub format: StatusFormat,
+    /// The nagios warning threshold, like 30d, 48h or 33% (defaults to the renewal threshold)
+    #[arg(long, value_name = "threshold")]
+    pub warning: Option<RenewThreshold>,
+    /// The nagios critical threshold (defaults to half of the warning threshold)
+    #[arg(long, value_name = "threshold")]
+    pub critical: Option<RenewThreshold>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum StatusFormat {
+    Text,
+    Json,
+    Prometheus,
+    Nagios,
+}
+
 #[derive(Debug, Clone, Parser)]
 pub struct CheckArgs {
     /// Only check specific certs
     pub certs: Vec<String>,
 }
 
+#[derive(Debug, Clone, Parser)]
+pub struct AccountArgs {
+    /// The name of the account, if multiple accounts are used
+    #[arg(long, default_value = DEFAULT_ACCOUNT)]
+    pub account: String,
+    /// The acme server of the account, defaults to the configured acme_url
+    #[arg(long = "acme-url", value_name = "url")]
+    pub acme_url: Option<String>,
+    #[command(subcommand)]
+    pub action: AccountAction,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum AccountAction {
+    /// Show the account registered at the acme server
+    Show,
+    /// Register a new account
+    Register,
+    /// Replace the contact emails of the account
+    UpdateContact(UpdateContactArgs),
+    /// Replace the account key with a newly generated one
+    KeyRollover,
+    /// Deactivate the account, this can't be undone
+    Deactivate(DeactivateArgs),
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct UpdateContactArgs {
+    /// The new contact emails, defaults to acme_email of the config
+    pub emails: Vec<String>,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct DeactivateArgs {
+    /// Confirm the account should be deactivated
+    #[arg(long)]
+    pub yes: bool,
+}
+
+#[derive(Debug, Clone, Parser)]
+pub struct RevokeArgs {
+    /// Sign the revocation with the private key of the certificate instead of the account key
+    #[arg(long)]
+    pub with_cert_key: bool,
+    /// The reason for the revocation
+    #[arg(long, value_enum)]
+    pub reason: Option<RevocationReason>,
+    /// Request a new certificate after revoking it
+    #[arg(long)]
+    pub reissue: bool,
+    /// The name of the certificate or a versioned folder in certs/
+    pub cert: String,
+}
+
+/// Revocation reason codes, see https://www.rfc-editor.org/rfc/rfc5280#section-5.3.1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum RevocationReason {
+    Unspecified,
+    KeyCompromise,
+    AffiliationChanged,
+    Superseded,
+    CessationOfOperation,
+    PrivilegeWithdrawn,
+}
+
+impl RevocationReason {
+    pub fn code(&self) -> u8 {
+        match self {
+            RevocationReason::Unspecified => 0,
+            RevocationReason::KeyCompromise => 1,
+            RevocationReason::AffiliationChanged => 3,
+            RevocationReason::Superseded => 4,
+            RevocationReason::CessationOfOperation => 5,
+            RevocationReason::PrivilegeWithdrawn => 9,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Parser)]
 pub struct Completions {
     pub shell: Shell,
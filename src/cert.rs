@@ -1,9 +1,122 @@
+use crate::client::base64url;
+use crate::config::{self, KeyType};
+use crate::csr::TLS_FEATURE_OID;
 use crate::errors::*;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::net::IpAddr;
+use x509_parser::extensions::{GeneralName, ParsedExtension};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Certificate {
+    key_type: Option<KeyType>,
+    private_key: Option<String>,
+    certificate: String,
+}
+
+impl Certificate {
+    pub fn new(key_type: KeyType, private_key: String, certificate: String) -> Certificate {
+        Certificate {
+            key_type: Some(key_type),
+            private_key: Some(private_key),
+            certificate,
+        }
+    }
+
+    /// A certificate issued for a custom csr, the private key is not known to us
+    pub fn without_key(key_type: Option<KeyType>, certificate: String) -> Certificate {
+        Certificate {
+            key_type,
+            private_key: None,
+            certificate,
+        }
+    }
+
+    pub fn key_type(&self) -> Option<KeyType> {
+        self.key_type
+    }
+
+    pub fn private_key(&self) -> Option<&str> {
+        self.private_key.as_deref()
+    }
+
+    pub fn certificate(&self) -> &str {
+        &self.certificate
+    }
+}
 
 #[derive(Debug, PartialEq)]
 pub struct CertInfo {
     pub not_before: String,
+    pub issued: time::OffsetDateTime,
     pub expires: time::OffsetDateTime,
+    pub must_staple: bool,
+    /// The certificate identifier for acme renewal information
+    pub ari_id: Option<String>,
+    /// The dns names and ip addresses in the subject alt names
+    pub names: Vec<String>,
+    pub issuer: String,
+    pub serial: String,
+}
+
+/// The difference between the configured names and the names of a certificate
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct NameDrift {
+    /// Configured names that the certificate isn't valid for
+    pub missing: Vec<String>,
+    /// Names in the certificate that are not configured anymore
+    pub extra: Vec<String>,
+}
+
+impl NameDrift {
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty()
+    }
+}
+
+impl fmt::Display for NameDrift {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names = self
+            .missing
+            .iter()
+            .map(|name| format!("+{name}"))
+            .chain(self.extra.iter().map(|name| format!("-{name}")))
+            .collect::<Vec<_>>();
+        write!(f, "{}", names.join(" "))
+    }
+}
+
+// Names are compared case-insensitive and ip addresses in their canonical form
+fn normalize_name(name: &str) -> String {
+    match config::parse_ip(name) {
+        Some(addr) => addr.to_string(),
+        None => name.to_ascii_lowercase(),
+    }
+}
+
+/// The unique identifier of a certificate, see https://www.rfc-editor.org/rfc/rfc9773#section-4.1
+fn ari_cert_id(key_identifier: &[u8], serial: &[u8]) -> String {
+    format!("{}.{}", base64url(key_identifier), base64url(serial))
+}
+
+// Check if the tls feature extension contains status_request (5)
+fn has_status_request(value: &[u8]) -> bool {
+    let Some(([0x30, len], mut features)) = value.split_first_chunk::<2>() else {
+        return false;
+    };
+    if features.len() != *len as usize {
+        return false;
+    }
+    while let Some(([0x02, len], rest)) = features.split_first_chunk::<2>() {
+        let Some((feature, rest)) = rest.split_at_checked(*len as usize) else {
+            return false;
+        };
+        if feature == [5] {
+            return true;
+        }
+        features = rest;
+    }
+    false
 }
 
 impl CertInfo {
@@ -14,12 +127,71 @@ impl CertInfo {
             .context("Failed to parse certificate")?;
         let validity = certificate.validity();
 
+        let must_staple = certificate
+            .extensions()
+            .iter()
+            .filter(|ext| ext.oid.to_id_string() == TLS_FEATURE_OID)
+            .any(|ext| has_status_request(ext.value));
+
+        let ari_id = certificate
+            .extensions()
+            .iter()
+            .find_map(|ext| match ext.parsed_extension() {
+                ParsedExtension::AuthorityKeyIdentifier(aki) => aki.key_identifier.as_ref(),
+                _ => None,
+            })
+            .map(|key_identifier| ari_cert_id(key_identifier.0, certificate.raw_serial()));
+
+        let mut names = Vec::new();
+        if let Some(san) = certificate
+            .subject_alternative_name()
+            .context("Failed to parse subject alt names")?
+        {
+            for name in &san.value.general_names {
+                match name {
+                    GeneralName::DNSName(name) => names.push(name.to_string()),
+                    GeneralName::IPAddress(addr) => {
+                        if let Ok(addr) = <[u8; 4]>::try_from(*addr) {
+                            names.push(IpAddr::from(addr).to_string());
+                        } else if let Ok(addr) = <[u8; 16]>::try_from(*addr) {
+                            names.push(IpAddr::from(addr).to_string());
+                        }
+                    }
+                    _ => (),
+                }
+            }
+        }
+
         Ok(CertInfo {
             not_before: validity.not_before.to_string(),
+            issued: validity.not_before.to_datetime(),
             expires: validity.not_after.to_datetime(),
+            must_staple,
+            ari_id,
+            names,
+            issuer: certificate.issuer().to_string(),
+            serial: certificate.raw_serial_as_string(),
         })
     }
 
+    /// Compare the names of the certificate with the configured names
+    pub fn name_drift(&self, configured: &[&str]) -> NameDrift {
+        let names = self
+            .names
+            .iter()
+            .map(|name| normalize_name(name))
+            .collect::<BTreeSet<_>>();
+        let configured = configured
+            .iter()
+            .map(|name| normalize_name(name))
+            .collect::<BTreeSet<_>>();
+
+        NameDrift {
+            missing: configured.difference(&names).cloned().collect(),
+            extra: names.difference(&configured).cloned().collect(),
+        }
+    }
+
     fn days_left_from(&self, now: time::OffsetDateTime) -> i64 {
         let dur = self.expires - now;
         dur.whole_days()
@@ -68,7 +240,13 @@ m9Iy/v8ftvQCp2l/qlk1/A==
             cert,
             CertInfo {
                 not_before: "Jan  1 00:00:00 1975 +00:00".to_string(),
+                issued: datetime(1975, Month::January, 1, 0, 0, 0),
                 expires: datetime(4096, Month::January, 1, 0, 0, 0),
+                must_staple: false,
+                ari_id: None,
+                names: vec![],
+                issuer: "CN=flowers are blooming in antarctica".to_string(),
+                serial: "66:dc:18:15:6d:83:6f:9b:63:99:ba:c3:6a:fd:43:cc:6c:1b:e8:b1".to_string(),
             }
         );
         assert_eq!(
@@ -112,7 +290,26 @@ DZSVIDNQ9IZBVfUfHHpoTgqF8qxrVB8a0/PSz7K5TDkDQP/Bfec=
             cert,
             CertInfo {
                 not_before: "May  7 16:14:37 2026 +00:00".to_string(),
+                issued: datetime(2026, Month::May, 7, 16, 14, 37),
                 expires: datetime(2026, Month::August, 5, 16, 14, 36),
+                must_staple: false,
+                ari_id: Some("rkie3IcdRKBv2qLlYHQEeMKcAIA.BRirJMSMrHnDgH6LMSv04ZwQ".to_string()),
+                names: [
+                    "cp.letsencrypt.org",
+                    "cp.root-x1.letsencrypt.org",
+                    "cps.letsencrypt.org",
+                    "cps.root-x1.letsencrypt.org",
+                    "lencr.org",
+                    "letsencrypt.com",
+                    "letsencrypt.org",
+                    "www.lencr.org",
+                    "www.letsencrypt.com",
+                    "www.letsencrypt.org",
+                ]
+                .map(String::from)
+                .to_vec(),
+                issuer: "C=US, O=Let's Encrypt, CN=E7".to_string(),
+                serial: "05:18:ab:24:c4:8c:ac:79:c3:80:7e:8b:31:2b:f4:e1:9c:10".to_string(),
             }
         );
         assert_eq!(
@@ -124,4 +321,67 @@ DZSVIDNQ9IZBVfUfHHpoTgqF8qxrVB8a0/PSz7K5TDkDQP/Bfec=
             81
         );
     }
+
+    #[test]
+    fn test_name_drift() {
+        let cert = CertInfo {
+            not_before: String::new(),
+            issued: datetime(2026, Month::May, 7, 0, 0, 0),
+            expires: datetime(2026, Month::August, 5, 0, 0, 0),
+            must_staple: false,
+            ari_id: None,
+            names: vec![
+                "example.com".to_string(),
+                "www.example.com".to_string(),
+                "2001:db8::1".to_string(),
+            ],
+            issuer: String::new(),
+            serial: String::new(),
+        };
+
+        let drift = cert.name_drift(&["Example.com", "www.example.com", "2001:0db8:0:0::1"]);
+        assert!(drift.is_empty());
+
+        // the name of the config is requested even if it's not listed in dns_names
+        let dns_names = ["www.example.com".to_string(), "2001:db8::1".to_string()];
+        let drift = cert.name_drift(&crate::config::cert_names("example.com", &dns_names));
+        assert!(drift.is_empty());
+
+        let drift = cert.name_drift(&["example.com", "api.example.com"]);
+        assert_eq!(
+            drift,
+            NameDrift {
+                missing: vec!["api.example.com".to_string()],
+                extra: vec!["2001:db8::1".to_string(), "www.example.com".to_string()],
+            }
+        );
+        assert_eq!(
+            drift.to_string(),
+            "+api.example.com -2001:db8::1 -www.example.com"
+        );
+    }
+
+    #[test]
+    fn test_ari_cert_id() {
+        let key_identifier = [
+            0x69, 0x88, 0x5b, 0x6b, 0x87, 0x46, 0x40, 0x41, 0xe1, 0xb3, 0x7b, 0x84, 0x7b, 0xa0,
+            0xae, 0x2c, 0xde, 0x01, 0xc8, 0xd4,
+        ];
+        let serial = [0x00, 0x87, 0x65, 0x43, 0x21];
+        assert_eq!(
+            ari_cert_id(&key_identifier, &serial),
+            "aYhba4dGQEHhs3uEe6CuLN4ByNQ.AIdlQyE"
+        );
+    }
+
+    #[test]
+    fn test_has_status_request() {
+        assert!(has_status_request(&[0x30, 0x03, 0x02, 0x01, 0x05]));
+        assert!(has_status_request(&[
+            0x30, 0x06, 0x02, 0x01, 0x11, 0x02, 0x01, 0x05
+        ]));
+        assert!(!has_status_request(&[0x30, 0x03, 0x02, 0x01, 0x11]));
+        assert!(!has_status_request(&[0x30, 0x05, 0x02, 0x01, 0x05]));
+        assert!(!has_status_request(&[]));
+    }
 }
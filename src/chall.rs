@@ -1,5 +1,7 @@
-use crate::config::Config;
+use crate::config::{self, CertConfig, ChallengeType, Config, Hook, HookOptions};
 use crate::errors::*;
+use crate::renew;
+use crate::tls_alpn;
 use rand::distr::slice::Choose;
 use rand::prelude::*;
 use std::fs;
@@ -13,6 +15,46 @@ pub fn valid_token(t: &str) -> bool {
     t.chars().all(|c| VALID_CHARS.contains(c))
 }
 
+#[inline]
+pub fn valid_domain(d: &str) -> bool {
+    !d.is_empty()
+        && !d.starts_with('.')
+        && !d.contains("..")
+        && d.chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+}
+
+/// Something that can make an acme challenge proof available to the acme server
+pub trait ChallengeSolver {
+    /// The challenge type that should be requested from the acme server
+    fn challenge_type(&self) -> ChallengeType;
+
+    /// Publish the proof for a specific domain
+    fn present(&mut self, domain: &str, token: &str, proof: &str) -> Result<()>;
+
+    /// Remove everything that was published by this solver
+    fn cleanup(&mut self) -> Result<()>;
+}
+
+pub fn solver_for(config: &Config, cert: &CertConfig) -> Result<Box<dyn ChallengeSolver>> {
+    match cert.challenge() {
+        ChallengeType::Http01 => Ok(Box::new(Challenge::new(config))),
+        ChallengeType::Dns01 => {
+            let add = cert
+                .dns_add
+                .clone()
+                .ok_or_else(|| anyhow!("dns-01 challenge requires a dns_add hook"))?;
+            let options = cert.hook_options(&config.system);
+            Ok(Box::new(DnsHook::new(
+                add,
+                cert.dns_remove.clone(),
+                options,
+            )))
+        }
+        ChallengeType::TlsAlpn01 => Ok(Box::new(TlsAlpnChallenge::new(config))),
+    }
+}
+
 pub struct Challenge {
     path: PathBuf,
     written: Vec<PathBuf>,
@@ -69,3 +111,188 @@ impl Challenge {
         Ok(())
     }
 }
+
+impl ChallengeSolver for Challenge {
+    fn challenge_type(&self) -> ChallengeType {
+        ChallengeType::Http01
+    }
+
+    fn present(&mut self, _domain: &str, token: &str, proof: &str) -> Result<()> {
+        self.write(token, proof)
+    }
+
+    fn cleanup(&mut self) -> Result<()> {
+        Challenge::cleanup(self)
+    }
+}
+
+/// Proofs for tls-alpn-01, the daemon generates the validation certificate from them
+pub struct TlsAlpnChallenge {
+    path: PathBuf,
+    written: Vec<PathBuf>,
+}
+
+impl TlsAlpnChallenge {
+    pub fn new(config: &Config) -> TlsAlpnChallenge {
+        let chall_dir = Path::new(&config.system.chall_dir);
+        TlsAlpnChallenge {
+            path: chall_dir.join("tls-alpn"),
+            written: Vec::new(),
+        }
+    }
+}
+
+impl ChallengeSolver for TlsAlpnChallenge {
+    fn challenge_type(&self) -> ChallengeType {
+        ChallengeType::TlsAlpn01
+    }
+
+    fn present(&mut self, domain: &str, _token: &str, proof: &str) -> Result<()> {
+        // ip addresses are validated with their reverse name as server name
+        let domain = match config::parse_ip(domain) {
+            Some(addr) => tls_alpn::reverse_name(&addr),
+            None => domain.to_string(),
+        };
+        let domain = domain.as_str();
+        if !valid_domain(domain) {
+            bail!("ACME server sent us malicious domain")
+        }
+
+        fs::create_dir_all(&self.path)
+            .with_context(|| anyhow!("Failed to create folder: {:?}", self.path))?;
+        let path = self.path.join(domain);
+        debug!("Writing tls-alpn challenge proof to {:?}", path);
+        fs::write(&path, proof).context("Failed to write challenge proof")?;
+
+        self.written.push(path);
+
+        Ok(())
+    }
+
+    fn cleanup(&mut self) -> Result<()> {
+        for path in self.written.drain(..) {
+            debug!("Deleting old challenge proof: {:?}", path);
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+#[inline]
+fn dns_record(domain: &str) -> String {
+    let domain = domain.strip_prefix("*.").unwrap_or(domain);
+    format!("_acme-challenge.{domain}")
+}
+
+/// Delegate the TXT record setup to external commands
+pub struct DnsHook {
+    add: Hook,
+    remove: Option<Hook>,
+    options: HookOptions,
+    written: Vec<(String, String, String)>,
+}
+
+impl DnsHook {
+    pub fn new(add: Hook, remove: Option<Hook>, options: HookOptions) -> DnsHook {
+        DnsHook {
+            add,
+            remove,
+            options,
+            written: Vec::new(),
+        }
+    }
+
+    fn execute(&self, hook: &Hook, domain: &str, record: &str, value: &str) -> Result<()> {
+        debug!("Executing dns hook {} for {:?}", hook, record);
+        let env = [
+            ("ACME_DNS_DOMAIN", domain.to_string()),
+            ("ACME_DNS_RECORD", record.to_string()),
+            ("ACME_DNS_VALUE", value.to_string()),
+        ];
+        renew::execute_hooks(std::slice::from_ref(hook), &env, &self.options, false)
+    }
+}
+
+impl ChallengeSolver for DnsHook {
+    fn challenge_type(&self) -> ChallengeType {
+        ChallengeType::Dns01
+    }
+
+    fn present(&mut self, domain: &str, _token: &str, proof: &str) -> Result<()> {
+        let record = dns_record(domain);
+        info!("Adding dns record {:?}", record);
+        self.execute(&self.add, domain, &record, proof)?;
+        self.written
+            .push((domain.to_string(), record, proof.to_string()));
+        Ok(())
+    }
+
+    fn cleanup(&mut self) -> Result<()> {
+        // keep going, so a single failure doesn't leave the other records behind
+        let mut failed = Vec::new();
+        for (domain, record, value) in std::mem::take(&mut self.written) {
+            if let Some(remove) = &self.remove {
+                info!("Removing dns record {:?}", record);
+                if let Err(err) = self.execute(remove, &domain, &record, &value) {
+                    failed.push(format!("{record:?} ({err:#})"));
+                }
+            }
+        }
+        if !failed.is_empty() {
+            bail!("Failed to remove dns records: {}", failed.join(", "));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::HookFailMode;
+
+    #[test]
+    fn test_valid_domain() {
+        assert!(valid_domain("example.com"));
+        assert!(valid_domain("www-1.example.com"));
+        assert!(!valid_domain(""));
+        assert!(!valid_domain(".."));
+        assert!(!valid_domain("../etc/passwd"));
+        assert!(!valid_domain("example.com/foo"));
+    }
+
+    #[test]
+    fn test_dns_record() {
+        assert_eq!(dns_record("example.com"), "_acme-challenge.example.com");
+        assert_eq!(dns_record("*.example.com"), "_acme-challenge.example.com");
+    }
+
+    #[test]
+    fn test_dns_cleanup() {
+        let tmp = tempfile::tempdir().unwrap();
+        let log = tmp.path().join("dns.log");
+        let remove = format!(r#"echo "$ACME_DNS_VALUE" >> "{}"; false"#, log.display());
+        let options = HookOptions {
+            timeout: Some(std::time::Duration::from_secs(1)),
+            retries: 0,
+            fail_mode: HookFailMode::Fail,
+        };
+        let mut hook = DnsHook::new("true".into(), Some(remove.into()), options);
+        hook.present("example.com", "", "a").unwrap();
+        hook.present("www.example.com", "", "b").unwrap();
+
+        let err = hook.cleanup().unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("Failed to remove dns records: \"_acme-challenge.example.com\"")
+        );
+        assert_eq!(fs::read_to_string(&log).unwrap(), "a\nb\n");
+
+        // records are only removed once
+        hook.cleanup().unwrap();
+
+        // a hanging hook is killed after the timeout
+        let mut hook = DnsHook::new("sleep 10".into(), None, options);
+        let err = hook.present("example.com", "", "a").unwrap_err();
+        assert!(err.to_string().ends_with("(Timed out after 1s)"), "{err:#}");
+    }
+}
@@ -1,14 +1,22 @@
 use crate::args::CheckArgs;
 use crate::chall::Challenge;
-use crate::config::Config;
+use crate::config::{self, ChallengeType, Config};
 use crate::errors::*;
 use std::collections::HashSet;
+use std::net::IpAddr;
 use std::time::Duration;
 
 const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
 
+fn challenge_url(name: &str, token: &str) -> String {
+    match config::parse_ip(name) {
+        Some(IpAddr::V6(addr)) => format!("http://[{addr}]/.well-known/acme-challenge/{token}"),
+        _ => format!("http://{name}/.well-known/acme-challenge/{token}"),
+    }
+}
+
 pub fn check(name: &str, token: &str) -> Result<()> {
-    let url = format!("http://{name}/.well-known/acme-challenge/{token}");
+    let url = challenge_url(name, token);
     let mut r = ureq::get(&url)
         .config()
         .timeout_global(Some(REQUEST_TIMEOUT))
@@ -38,7 +46,25 @@ pub fn run(config: Config, mut args: CheckArgs) -> Result<()> {
 
     let filter = args.certs.drain(..).collect::<HashSet<_>>();
     for cert in config.filter_certs(&filter) {
+        let challenge = cert.challenge();
+        if challenge != ChallengeType::Http01 {
+            info!(
+                "Skipping {:?}: certificate is validated with {}, not http-01",
+                cert.name,
+                challenge.as_str()
+            );
+            continue;
+        }
+
         for dns_name in &cert.dns_names {
+            if config::is_wildcard(dns_name) {
+                info!(
+                    "Skipping {:?} -> {:?}: wildcard names can't be checked over http",
+                    cert.name, dns_name
+                );
+                continue;
+            }
+
             if let Err(err) = check(dns_name, &token) {
                 error!(
                     "Check failed ({:?} -> {:?}): {:#}",
@@ -54,3 +80,24 @@ pub fn run(config: Config, mut args: CheckArgs) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_challenge_url() {
+        assert_eq!(
+            challenge_url("example.com", "foo"),
+            "http://example.com/.well-known/acme-challenge/foo"
+        );
+        assert_eq!(
+            challenge_url("192.0.2.1", "foo"),
+            "http://192.0.2.1/.well-known/acme-challenge/foo"
+        );
+        assert_eq!(
+            challenge_url("2001:db8::1", "foo"),
+            "http://[2001:db8::1]/.well-known/acme-challenge/foo"
+        );
+    }
+}
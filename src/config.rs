@@ -1,16 +1,22 @@
 use crate::args::Args;
+use crate::cert::CertInfo;
 use crate::errors::*;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 use std::ffi::OsStr;
+use std::fmt;
 use std::fs;
+use std::net::IpAddr;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use time::{Duration, OffsetDateTime};
 
 const LETSENCRYPT: &str = "https://acme-v02.api.letsencrypt.org/directory";
 // const LETSENCRYPT_STAGING: &str = "https://acme-staging-v02.api.letsencrypt.org/directory";
 pub const DEFAULT_RENEW_IF_DAYS_LEFT: i64 = 30;
 pub const BIND_ALL_PORT_80: &str = "[::]:80";
+pub const DEFAULT_ACCOUNT: &str = "default";
 
 #[derive(Debug, PartialEq, Eq, Deserialize)]
 pub struct ConfigFile {
@@ -25,17 +31,162 @@ pub struct AcmeConfig {
     pub acme_email: Option<String>,
     pub acme_url: String,
     pub renew_if_days_left: i64,
+    pub renew_before: Option<RenewThreshold>,
+    pub key_type: KeyType,
+    pub eab_kid: Option<String>,
+    pub eab_hmac_key: Option<String>,
+    pub eab_hmac_key_file: Option<PathBuf>,
+    pub profile: Option<String>,
 }
 
 #[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SystemConfig {
     pub addr: Option<String>,
+    pub tls_addr: Option<String>,
+    pub metrics_addr: Option<String>,
     pub data_dir: PathBuf,
     pub chall_dir: PathBuf,
     #[serde(default)]
-    pub exec: Vec<String>,
+    pub exec: Vec<Hook>,
     #[serde(default)]
-    pub exec_extra: Vec<String>,
+    pub exec_extra: Vec<Hook>,
+    #[serde(default)]
+    pub pre_exec: Vec<Hook>,
+    #[serde(default)]
+    pub post_exec: Vec<Hook>,
+    #[serde(default)]
+    pub on_failure: Vec<Hook>,
+    pub hook_timeout: Option<u64>,
+    #[serde(default)]
+    pub hook_retries: u32,
+    #[serde(default)]
+    pub hook_fail_mode: HookFailMode,
+}
+
+impl SystemConfig {
+    /// The hook options of the global hooks
+    pub fn hook_options(&self) -> HookOptions {
+        HookOptions {
+            timeout: self.hook_timeout.map(std::time::Duration::from_secs),
+            retries: self.hook_retries,
+            fail_mode: self.hook_fail_mode,
+        }
+    }
+}
+
+/// What should happen if a hook fails, ordered from least to most strict
+#[derive(Debug, Default, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HookFailMode {
+    /// Only log the failure at debug level
+    Ignore,
+    /// Log a warning but consider the renewal successful
+    Warn,
+    /// Report the renewal as failed
+    #[default]
+    Fail,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct HookOptions {
+    pub timeout: Option<std::time::Duration>,
+    pub retries: u32,
+    pub fail_mode: HookFailMode,
+}
+
+impl HookOptions {
+    /// Combine the options of a hook that is shared by multiple certificates
+    pub fn merge(self, other: HookOptions) -> HookOptions {
+        HookOptions {
+            // no timeout is the longest timeout
+            timeout: self.timeout.zip(other.timeout).map(|(a, b)| a.max(b)),
+            retries: self.retries.max(other.retries),
+            fail_mode: self.fail_mode.max(other.fail_mode),
+        }
+    }
+}
+
+/// The command of a hook, either executed with `sh -c` or directly
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum HookCmd {
+    Shell(String),
+    Argv(Vec<String>),
+}
+
+/// A hook can be configured as a shell command, an argv array or a table with extra options
+#[derive(Debug, PartialEq, Eq, Clone, Deserialize)]
+#[serde(untagged)]
+enum HookDef {
+    Cmd(HookCmd),
+    Table {
+        cmd: HookCmd,
+        user: Option<String>,
+        working_dir: Option<PathBuf>,
+        #[serde(default)]
+        env: BTreeMap<String, String>,
+    },
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[serde(from = "HookDef")]
+pub struct Hook {
+    pub cmd: HookCmd,
+    /// Execute the hook as this user instead of root
+    pub user: Option<String>,
+    pub working_dir: Option<PathBuf>,
+    pub env: BTreeMap<String, String>,
+}
+
+impl From<HookDef> for Hook {
+    fn from(def: HookDef) -> Hook {
+        match def {
+            HookDef::Cmd(cmd) => Hook::from(cmd),
+            HookDef::Table {
+                cmd,
+                user,
+                working_dir,
+                env,
+            } => Hook {
+                cmd,
+                user,
+                working_dir,
+                env,
+            },
+        }
+    }
+}
+
+impl From<HookCmd> for Hook {
+    fn from(cmd: HookCmd) -> Hook {
+        Hook {
+            cmd,
+            user: None,
+            working_dir: None,
+            env: BTreeMap::new(),
+        }
+    }
+}
+
+impl From<String> for Hook {
+    fn from(cmd: String) -> Hook {
+        Hook::from(HookCmd::Shell(cmd))
+    }
+}
+
+impl From<&str> for Hook {
+    fn from(cmd: &str) -> Hook {
+        Hook::from(cmd.to_string())
+    }
+}
+
+impl fmt::Display for Hook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.cmd {
+            HookCmd::Shell(cmd) => write!(f, "{cmd:?}"),
+            HookCmd::Argv(argv) => write!(f, "{argv:?}"),
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Deserialize)]
@@ -73,14 +224,424 @@ fn load_from_folder<P: AsRef<Path>>(path: P) -> Result<Vec<CertConfigFile>> {
     Ok(configs)
 }
 
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum ChallengeType {
+    #[default]
+    #[serde(rename = "http-01")]
+    Http01,
+    #[serde(rename = "dns-01")]
+    Dns01,
+    #[serde(rename = "tls-alpn-01")]
+    TlsAlpn01,
+}
+
+impl ChallengeType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChallengeType::Http01 => "http-01",
+            ChallengeType::Dns01 => "dns-01",
+            ChallengeType::TlsAlpn01 => "tls-alpn-01",
+        }
+    }
+}
+
+/// When a certificate should be renewed, like 30d, 48h or 33% of its lifetime
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum RenewThreshold {
+    Days(i64),
+    Hours(i64),
+    Percent(u8),
+}
+
+impl RenewThreshold {
+    pub fn should_renew(&self, cert: &CertInfo, now: OffsetDateTime) -> bool {
+        let left = cert.expires - now;
+        match self {
+            RenewThreshold::Days(days) => left.whole_days() <= *days,
+            RenewThreshold::Hours(hours) => left.whole_hours() <= *hours,
+            RenewThreshold::Percent(percent) => {
+                let lifetime = cert.expires - cert.issued;
+                left <= lifetime * (*percent as f64 / 100.0)
+            }
+        }
+    }
+
+    /// The time before expiry this threshold refers to for a specific certificate
+    pub fn duration(&self, cert: &CertInfo) -> Duration {
+        match self {
+            RenewThreshold::Days(days) => Duration::days(*days),
+            RenewThreshold::Hours(hours) => Duration::hours(*hours),
+            RenewThreshold::Percent(percent) => {
+                (cert.expires - cert.issued) * (*percent as f64 / 100.0)
+            }
+        }
+    }
+
+    pub fn half(&self) -> RenewThreshold {
+        match self {
+            RenewThreshold::Days(days) => RenewThreshold::Hours(days * 12),
+            RenewThreshold::Hours(hours) => RenewThreshold::Hours(hours / 2),
+            RenewThreshold::Percent(percent) => RenewThreshold::Percent(percent / 2),
+        }
+    }
+}
+
+impl FromStr for RenewThreshold {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<RenewThreshold> {
+        let threshold = if let Some(days) = s.strip_suffix('d') {
+            RenewThreshold::Days(days.parse()?)
+        } else if let Some(hours) = s.strip_suffix('h') {
+            RenewThreshold::Hours(hours.parse()?)
+        } else if let Some(percent) = s.strip_suffix('%') {
+            let percent = percent.parse()?;
+            if percent > 100 {
+                bail!("Percentage can't be more than 100%");
+            }
+            RenewThreshold::Percent(percent)
+        } else {
+            bail!("Unknown unit, expected d, h or %")
+        };
+        Ok(threshold)
+    }
+}
+
+impl TryFrom<String> for RenewThreshold {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<RenewThreshold> {
+        s.parse()
+            .with_context(|| anyhow!("Invalid renew threshold: {:?}", s))
+    }
+}
+
+impl fmt::Display for RenewThreshold {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RenewThreshold::Days(days) => write!(f, "{days}d"),
+            RenewThreshold::Hours(hours) => write!(f, "{hours}h"),
+            RenewThreshold::Percent(percent) => write!(f, "{percent}%"),
+        }
+    }
+}
+
+impl From<RenewThreshold> for String {
+    fn from(threshold: RenewThreshold) -> String {
+        threshold.to_string()
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum KeyType {
+    #[serde(rename = "ecdsa-p256")]
+    EcdsaP256,
+    #[default]
+    #[serde(rename = "ecdsa-p384")]
+    EcdsaP384,
+    #[serde(rename = "rsa-2048")]
+    Rsa2048,
+    #[serde(rename = "rsa-4096")]
+    Rsa4096,
+}
+
+impl KeyType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KeyType::EcdsaP256 => "ecdsa-p256",
+            KeyType::EcdsaP384 => "ecdsa-p384",
+            KeyType::Rsa2048 => "rsa-2048",
+            KeyType::Rsa4096 => "rsa-4096",
+        }
+    }
+
+    /// The folder name if a certificate is issued for multiple key types
+    pub fn variant(&self) -> &'static str {
+        match self {
+            KeyType::EcdsaP256 | KeyType::EcdsaP384 => "ecdsa",
+            KeyType::Rsa2048 | KeyType::Rsa4096 => "rsa",
+        }
+    }
+}
+
+/// A single certificate that is issued for a certificate config
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct CertVariant {
+    pub key_type: KeyType,
+    /// The folder inside of live/<name>/, if there are multiple variants
+    pub folder: Option<&'static str>,
+}
+
+impl CertVariant {
+    pub fn display_name(&self, name: &str) -> String {
+        if let Some(folder) = self.folder {
+            format!("{name}/{folder}")
+        } else {
+            name.to_string()
+        }
+    }
+}
+
+impl FromStr for KeyType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<KeyType> {
+        match s {
+            "ecdsa-p256" => Ok(KeyType::EcdsaP256),
+            "ecdsa-p384" => Ok(KeyType::EcdsaP384),
+            "rsa-2048" => Ok(KeyType::Rsa2048),
+            "rsa-4096" => Ok(KeyType::Rsa4096),
+            _ => bail!("Unknown key type: {:?}", s),
+        }
+    }
+}
+
+pub fn valid_account_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// A fully qualified hostname, acme servers don't issue certificates for single labels
+pub fn valid_hostname(name: &str) -> bool {
+    name.len() <= 253
+        && name.split('.').count() >= 2
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+#[inline]
+pub fn is_wildcard(name: &str) -> bool {
+    name.starts_with("*.")
+}
+
+/// The primary name followed by the alt names, without duplicates
+pub fn cert_names<'a>(primary_name: &'a str, alt_names: &'a [String]) -> Vec<&'a str> {
+    let mut names = vec![primary_name];
+    for name in alt_names {
+        if !names.contains(&name.as_str()) {
+            names.push(name);
+        }
+    }
+    names
+}
+
+/// Names that are ip addresses are requested as ip identifiers
+#[inline]
+pub fn parse_ip(name: &str) -> Option<IpAddr> {
+    name.parse().ok()
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+#[cfg_attr(test, derive(Default))]
 pub struct CertConfig {
     pub name: String,
     pub dns_names: Vec<String>,
     #[serde(default)]
     pub must_staple: bool,
     #[serde(default)]
-    pub exec: Vec<String>,
+    pub exec: Vec<Hook>,
+    #[serde(default)]
+    pub exec_per_cert: bool,
+    #[serde(default)]
+    pub pre_exec: Vec<Hook>,
+    #[serde(default)]
+    pub post_exec: Vec<Hook>,
+    #[serde(default)]
+    pub on_failure: Vec<Hook>,
+    pub challenge: Option<ChallengeType>,
+    pub dns_add: Option<Hook>,
+    pub dns_remove: Option<Hook>,
+    pub key_type: Option<KeyType>,
+    #[serde(default)]
+    pub key_types: Vec<KeyType>,
+    #[serde(default)]
+    pub reuse_key: bool,
+    pub csr: Option<PathBuf>,
+    pub acme_url: Option<String>,
+    pub account: Option<String>,
+    pub profile: Option<String>,
+    pub renew_before: Option<RenewThreshold>,
+    pub hook_timeout: Option<u64>,
+    pub hook_retries: Option<u32>,
+    pub hook_fail_mode: Option<HookFailMode>,
+}
+
+impl CertConfig {
+    /// The configured challenge type, wildcard certificates default to dns-01
+    pub fn challenge(&self) -> ChallengeType {
+        if let Some(challenge) = self.challenge {
+            challenge
+        } else if self.has_wildcards() {
+            ChallengeType::Dns01
+        } else {
+            ChallengeType::Http01
+        }
+    }
+
+    /// The configured key type, falls back to the global default
+    pub fn key_type(&self, acme: &AcmeConfig) -> KeyType {
+        self.key_type.unwrap_or(acme.key_type)
+    }
+
+    /// The acme server for this certificate, falls back to the global default
+    pub fn acme_url<'a>(&'a self, acme: &'a AcmeConfig) -> &'a str {
+        self.acme_url.as_deref().unwrap_or(&acme.acme_url)
+    }
+
+    /// The acme profile for this certificate, falls back to the global default
+    pub fn profile<'a>(&'a self, acme: &'a AcmeConfig) -> Option<&'a str> {
+        self.profile.as_deref().or(acme.profile.as_deref())
+    }
+
+    /// When this certificate should be renewed, falls back to renew_if_days_left
+    pub fn renew_threshold(&self, acme: &AcmeConfig) -> RenewThreshold {
+        self.renew_before
+            .or(acme.renew_before)
+            .unwrap_or(RenewThreshold::Days(acme.renew_if_days_left))
+    }
+
+    /// The hook options of this certificate, falls back to the global options
+    pub fn hook_options(&self, system: &SystemConfig) -> HookOptions {
+        let global = system.hook_options();
+        HookOptions {
+            timeout: self
+                .hook_timeout
+                .map(std::time::Duration::from_secs)
+                .or(global.timeout),
+            retries: self.hook_retries.unwrap_or(global.retries),
+            fail_mode: self.hook_fail_mode.unwrap_or(global.fail_mode),
+        }
+    }
+
+    /// The name of the account at the acme server
+    pub fn account(&self) -> &str {
+        self.account.as_deref().unwrap_or(DEFAULT_ACCOUNT)
+    }
+
+    /// The certificates that should be issued for this config
+    pub fn variants(&self, acme: &AcmeConfig) -> Vec<CertVariant> {
+        if self.key_types.is_empty() {
+            vec![CertVariant {
+                key_type: self.key_type(acme),
+                folder: None,
+            }]
+        } else {
+            self.key_types
+                .iter()
+                .map(|key_type| CertVariant {
+                    key_type: *key_type,
+                    folder: Some(key_type.variant()),
+                })
+                .collect()
+        }
+    }
+
+    /// The names the certificate is requested for, the name of the config always comes first
+    pub fn names(&self) -> Vec<&str> {
+        cert_names(&self.name, &self.dns_names)
+    }
+
+    pub fn has_wildcards(&self) -> bool {
+        self.dns_names.iter().any(|name| is_wildcard(name))
+    }
+
+    pub fn has_ip_addresses(&self) -> bool {
+        self.names()
+            .into_iter()
+            .any(|name| parse_ip(name).is_some())
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.name.is_empty() || self.name.starts_with('.') || self.name.contains('/') {
+            bail!("Certificate name is not allowed: {:?}", self.name);
+        }
+        if self.name.contains('*') {
+            bail!(
+                "Certificate name must not contain a wildcard, use the wildcard in dns_names instead: {:?}",
+                self.name
+            );
+        }
+
+        if parse_ip(&self.name).is_none() && !valid_hostname(&self.name) {
+            bail!(
+                "Certificate name is neither a hostname nor an ip address: {:?}",
+                self.name
+            );
+        }
+
+        for name in &self.dns_names {
+            let base = name.strip_prefix("*.").unwrap_or(name);
+            if base.is_empty() || base.contains('*') {
+                bail!(
+                    "Wildcards are only allowed as the left-most label: {:?}",
+                    name
+                );
+            }
+            if is_wildcard(name) && parse_ip(base).is_some() {
+                bail!("Wildcards can't be used with ip addresses: {:?}", name);
+            }
+            if parse_ip(base).is_none() && !valid_hostname(base) {
+                bail!("Name is neither a hostname nor an ip address: {:?}", name);
+            }
+        }
+
+        if self.key_type.is_some() && !self.key_types.is_empty() {
+            bail!("key_type and key_types can't be used at the same time");
+        }
+        if let Some(account) = &self.account
+            && !valid_account_name(account)
+        {
+            bail!("Account name is not allowed: {:?}", account);
+        }
+
+        if self.csr.is_some()
+            && (self.key_type.is_some()
+                || !self.key_types.is_empty()
+                || self.reuse_key
+                || self.must_staple)
+        {
+            bail!(
+                "A custom csr can't be combined with key_type, key_types, reuse_key or must_staple"
+            );
+        }
+        let mut variants = HashSet::new();
+        for key_type in &self.key_types {
+            if !variants.insert(key_type.variant()) {
+                bail!(
+                    "key_types can only contain one {} key type",
+                    key_type.variant()
+                );
+            }
+        }
+
+        match self.challenge() {
+            challenge @ (ChallengeType::Http01 | ChallengeType::TlsAlpn01)
+                if self.has_wildcards() =>
+            {
+                bail!(
+                    "Wildcard names can't be validated with {}, use dns-01 instead",
+                    challenge.as_str()
+                );
+            }
+            ChallengeType::Dns01 if self.has_ip_addresses() => {
+                bail!(
+                    "Ip addresses can't be validated with dns-01, use http-01 or tls-alpn-01 instead"
+                );
+            }
+            ChallengeType::Dns01 if self.dns_add.is_none() => {
+                bail!("The dns-01 challenge requires a dns_add hook");
+            }
+            _ => Ok(()),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -105,6 +666,7 @@ pub fn load(args: Args) -> Result<Config> {
     let settings = config::Config::builder()
         .set_default("acme.acme_url", LETSENCRYPT)?
         .set_default("acme.renew_if_days_left", DEFAULT_RENEW_IF_DAYS_LEFT)?
+        .set_default("acme.key_type", KeyType::default().as_str())?
         .set_default("system.data_dir", "/var/lib/acme-redirect")?
         .set_default("system.chall_dir", "/run/acme-redirect")?
         .add_source(config::File::new(&args.config, config::FileFormat::Toml))
@@ -122,7 +684,12 @@ pub fn load(args: Args) -> Result<Config> {
     let certs = load_from_folder(&args.config_dir)?
         .into_iter()
         .map(|c| c.cert)
-        .collect();
+        .collect::<Vec<_>>();
+
+    for cert in &certs {
+        cert.validate()
+            .with_context(|| anyhow!("Invalid certificate config {:?}", cert.name))?;
+    }
 
     Ok(Config {
         certs,
@@ -154,8 +721,435 @@ mod tests {
                     dns_names: vec!["example.com".to_string(), "www.example.com".to_string(),],
                     must_staple: false,
                     exec: vec![],
+                    exec_per_cert: false,
+                    pre_exec: vec![],
+                    post_exec: vec![],
+                    on_failure: vec![],
+                    challenge: None,
+                    dns_add: None,
+                    dns_remove: None,
+                    key_type: None,
+                    key_types: vec![],
+                    reuse_key: false,
+                    csr: None,
+                    acme_url: None,
+                    account: None,
+                    profile: None,
+                    renew_before: None,
+                    hook_timeout: None,
+                    hook_retries: None,
+                    hook_fail_mode: None,
                 },
             }
         );
     }
+
+    #[test]
+    fn dns_cert_conf() {
+        let conf = load_str::<CertConfigFile>(
+            r#"
+            [cert]
+            name = "example.com"
+            dns_names = ["example.com"]
+            challenge = "dns-01"
+            dns_add = "/usr/local/bin/dns-hook add"
+            dns_remove = { cmd = ["/usr/local/bin/dns-hook", "remove"], user = "dns" }
+        "#,
+        )
+        .unwrap();
+
+        assert_eq!(conf.cert.challenge(), ChallengeType::Dns01);
+        assert_eq!(
+            conf.cert.dns_add,
+            Some(Hook::from("/usr/local/bin/dns-hook add"))
+        );
+        assert_eq!(
+            conf.cert.dns_remove,
+            Some(Hook {
+                cmd: HookCmd::Argv(vec![
+                    "/usr/local/bin/dns-hook".to_string(),
+                    "remove".to_string()
+                ]),
+                user: Some("dns".to_string()),
+                working_dir: None,
+                env: BTreeMap::new(),
+            })
+        );
+        conf.cert.validate().unwrap();
+    }
+
+    #[test]
+    fn key_type_cert_conf() {
+        let conf = load_str::<CertConfigFile>(
+            r#"
+            [cert]
+            name = "example.com"
+            dns_names = ["example.com"]
+            key_type = "rsa-4096"
+        "#,
+        )
+        .unwrap();
+
+        let acme = AcmeConfig::default();
+        assert_eq!(acme.key_type, KeyType::EcdsaP384);
+        assert_eq!(conf.cert.key_type(&acme), KeyType::Rsa4096);
+        assert_eq!("rsa-4096".parse::<KeyType>().unwrap(), KeyType::Rsa4096);
+        assert!("rsa-1024".parse::<KeyType>().is_err());
+    }
+
+    #[test]
+    fn dual_key_types_cert_conf() {
+        let conf = load_str::<CertConfigFile>(
+            r#"
+            [cert]
+            name = "example.com"
+            dns_names = ["example.com"]
+            key_types = ["rsa-2048", "ecdsa-p256"]
+        "#,
+        )
+        .unwrap();
+        conf.cert.validate().unwrap();
+
+        assert_eq!(
+            conf.cert.variants(&AcmeConfig::default()),
+            &[
+                CertVariant {
+                    key_type: KeyType::Rsa2048,
+                    folder: Some("rsa"),
+                },
+                CertVariant {
+                    key_type: KeyType::EcdsaP256,
+                    folder: Some("ecdsa"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn conflicting_key_types() {
+        let conf = load_str::<CertConfigFile>(
+            r#"
+            [cert]
+            name = "example.com"
+            dns_names = ["example.com"]
+            key_types = ["rsa-2048", "rsa-4096"]
+        "#,
+        )
+        .unwrap();
+        assert!(conf.cert.validate().is_err());
+    }
+
+    #[test]
+    fn hooks_cert_conf() {
+        let conf = load_str::<CertConfigFile>(
+            r#"
+            [cert]
+            name = "example.com"
+            dns_names = ["example.com"]
+            exec = [
+                "systemctl reload nginx",
+                ["systemctl", "reload", "nginx"],
+                { cmd = ["cp", "fullchain", "/srv/certs/"], user = "www-data", working_dir = "/var/lib/acme-redirect/live/example.com", env = { FOO = "bar" } },
+            ]
+        "#,
+        )
+        .unwrap();
+
+        let argv = HookCmd::Argv(vec![
+            "systemctl".to_string(),
+            "reload".to_string(),
+            "nginx".to_string(),
+        ]);
+        assert_eq!(
+            conf.cert.exec,
+            [
+                Hook::from("systemctl reload nginx"),
+                Hook::from(argv),
+                Hook {
+                    cmd: HookCmd::Argv(vec![
+                        "cp".to_string(),
+                        "fullchain".to_string(),
+                        "/srv/certs/".to_string(),
+                    ]),
+                    user: Some("www-data".to_string()),
+                    working_dir: Some(PathBuf::from("/var/lib/acme-redirect/live/example.com")),
+                    env: BTreeMap::from([("FOO".to_string(), "bar".to_string())]),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn csr_cert_conf() {
+        let conf = load_str::<CertConfigFile>(
+            r#"
+            [cert]
+            name = "example.com"
+            dns_names = ["example.com"]
+            csr = "/etc/ssl/example.com.csr"
+        "#,
+        )
+        .unwrap();
+        assert_eq!(
+            conf.cert.csr.as_deref(),
+            Some(Path::new("/etc/ssl/example.com.csr"))
+        );
+        conf.cert.validate().unwrap();
+
+        let mut cert = conf.cert.clone();
+        cert.reuse_key = true;
+        assert!(cert.validate().is_err());
+
+        // the extensions are decided by the custom csr
+        let mut cert = conf.cert;
+        cert.must_staple = true;
+        assert!(cert.validate().is_err());
+    }
+
+    #[test]
+    fn account_cert_conf() {
+        let conf = load_str::<CertConfigFile>(
+            r#"
+            [cert]
+            name = "example.com"
+            dns_names = ["example.com"]
+            acme_url = "https://acme-staging-v02.api.letsencrypt.org/directory"
+            account = "staging"
+        "#,
+        )
+        .unwrap();
+        conf.cert.validate().unwrap();
+
+        let acme = AcmeConfig {
+            acme_url: LETSENCRYPT.to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            conf.cert.acme_url(&acme),
+            "https://acme-staging-v02.api.letsencrypt.org/directory"
+        );
+        assert_eq!(conf.cert.account(), "staging");
+
+        let mut cert = conf.cert;
+        cert.acme_url = None;
+        cert.account = None;
+        assert_eq!(cert.acme_url(&acme), LETSENCRYPT);
+        assert_eq!(cert.account(), DEFAULT_ACCOUNT);
+
+        cert.account = Some("../foo".to_string());
+        assert!(cert.validate().is_err());
+    }
+
+    #[test]
+    fn profile_cert_conf() {
+        let conf = load_str::<CertConfigFile>(
+            r#"
+            [cert]
+            name = "example.com"
+            dns_names = ["example.com"]
+            profile = "shortlived"
+            renew_before = "50%"
+        "#,
+        )
+        .unwrap();
+
+        let acme = AcmeConfig {
+            renew_if_days_left: 30,
+            ..Default::default()
+        };
+        assert_eq!(conf.cert.profile(&acme), Some("shortlived"));
+        assert_eq!(
+            conf.cert.renew_threshold(&acme),
+            RenewThreshold::Percent(50)
+        );
+
+        let mut cert = conf.cert;
+        cert.profile = None;
+        cert.renew_before = None;
+        assert_eq!(cert.profile(&acme), None);
+        assert_eq!(cert.renew_threshold(&acme), RenewThreshold::Days(30));
+    }
+
+    #[test]
+    fn test_renew_threshold() {
+        assert_eq!(
+            "30d".parse::<RenewThreshold>().unwrap(),
+            RenewThreshold::Days(30)
+        );
+        assert_eq!(
+            "48h".parse::<RenewThreshold>().unwrap(),
+            RenewThreshold::Hours(48)
+        );
+        assert_eq!(
+            "33%".parse::<RenewThreshold>().unwrap(),
+            RenewThreshold::Percent(33)
+        );
+        assert!("101%".parse::<RenewThreshold>().is_err());
+        assert!("30".parse::<RenewThreshold>().is_err());
+
+        // a certificate that is valid for 6 days
+        let cert = CertInfo {
+            not_before: String::new(),
+            issued: time::macros::datetime!(2025-01-01 00:00:00 UTC),
+            expires: time::macros::datetime!(2025-01-07 00:00:00 UTC),
+            must_staple: false,
+            ari_id: None,
+            names: vec![],
+            issuer: String::new(),
+            serial: String::new(),
+        };
+        let now = time::macros::datetime!(2025-01-04 12:00:00 UTC);
+        assert!(RenewThreshold::Percent(50).should_renew(&cert, now));
+        assert!(!RenewThreshold::Percent(33).should_renew(&cert, now));
+        assert!(RenewThreshold::Hours(60).should_renew(&cert, now));
+        assert!(!RenewThreshold::Hours(48).should_renew(&cert, now));
+        assert!(RenewThreshold::Days(2).should_renew(&cert, now));
+        assert!(!RenewThreshold::Days(1).should_renew(&cert, now));
+
+        assert_eq!(
+            RenewThreshold::Percent(50).duration(&cert),
+            Duration::days(3)
+        );
+        assert_eq!(RenewThreshold::Days(2).half(), RenewThreshold::Hours(24));
+        assert_eq!(
+            RenewThreshold::Percent(33).half(),
+            RenewThreshold::Percent(16)
+        );
+    }
+
+    #[test]
+    fn wildcard_cert_conf() {
+        let conf = load_str::<CertConfigFile>(
+            r#"
+            [cert]
+            name = "example.com"
+            dns_names = ["example.com", "*.example.com"]
+            dns_add = "/usr/local/bin/dns-hook add"
+        "#,
+        )
+        .unwrap();
+
+        assert!(conf.cert.has_wildcards());
+        assert_eq!(conf.cert.challenge(), ChallengeType::Dns01);
+        conf.cert.validate().unwrap();
+    }
+
+    #[test]
+    fn wildcard_requires_dns_challenge() {
+        let conf = load_str::<CertConfigFile>(
+            r#"
+            [cert]
+            name = "example.com"
+            dns_names = ["*.example.com"]
+            challenge = "http-01"
+        "#,
+        )
+        .unwrap();
+        assert!(conf.cert.validate().is_err());
+    }
+
+    #[test]
+    fn invalid_wildcards() {
+        for name in [
+            "*",
+            "*.",
+            "foo.*.example.com",
+            "*.*.example.com",
+            "f*o.example.com",
+            "*.192.0.2.1",
+        ] {
+            let cert = CertConfig {
+                name: "example.com".to_string(),
+                dns_names: vec![name.to_string()],
+                challenge: Some(ChallengeType::Dns01),
+                dns_add: Some("true".into()),
+                ..Default::default()
+            };
+            assert!(cert.validate().is_err(), "{name:?} should be rejected");
+        }
+    }
+
+    #[test]
+    fn ip_address_cert_conf() {
+        let conf = load_str::<CertConfigFile>(
+            r#"
+            [cert]
+            name = "admin.example.com"
+            dns_names = ["192.0.2.1", "2001:db8::1", "admin.example.com"]
+        "#,
+        )
+        .unwrap();
+
+        assert!(conf.cert.has_ip_addresses());
+        assert!(!conf.cert.has_wildcards());
+        assert_eq!(conf.cert.challenge(), ChallengeType::Http01);
+        conf.cert.validate().unwrap();
+
+        let mut cert = conf.cert;
+        cert.name = "192.0.2.1".to_string();
+        cert.validate().unwrap();
+    }
+
+    #[test]
+    fn invalid_names() {
+        let conf = load_str::<CertConfigFile>(
+            r#"
+            [cert]
+            name = "example.com"
+            dns_names = ["example.com"]
+        "#,
+        )
+        .unwrap();
+        conf.cert.validate().unwrap();
+
+        for name in [
+            "admin",
+            "example.com.",
+            "-example.com",
+            "exa_mple.com",
+            "example..com",
+        ] {
+            let mut cert = conf.cert.clone();
+            cert.name = name.to_string();
+            assert!(cert.validate().is_err(), "{name:?} should be rejected");
+
+            let mut cert = conf.cert.clone();
+            cert.dns_names.push(name.to_string());
+            assert!(cert.validate().is_err(), "{name:?} should be rejected");
+        }
+    }
+
+    #[test]
+    fn cert_names_include_name() {
+        let conf = load_str::<CertConfigFile>(
+            r#"
+            [cert]
+            name = "example.com"
+            dns_names = ["www.example.com"]
+        "#,
+        )
+        .unwrap();
+        assert_eq!(conf.cert.names(), &["example.com", "www.example.com"]);
+
+        let dns_names = ["www.example.com".to_string(), "example.com".to_string()];
+        assert_eq!(
+            cert_names("example.com", &dns_names),
+            &["example.com", "www.example.com"]
+        );
+    }
+
+    #[test]
+    fn ip_address_rejects_dns_challenge() {
+        let conf = load_str::<CertConfigFile>(
+            r#"
+            [cert]
+            name = "192.0.2.1"
+            dns_names = ["192.0.2.1"]
+            challenge = "dns-01"
+            dns_add = "/usr/local/bin/dns-hook add"
+        "#,
+        )
+        .unwrap();
+        assert!(conf.cert.validate().is_err());
+    }
 }
@@ -0,0 +1,158 @@
+use crate::config::{self, KeyType};
+use crate::errors::*;
+use openssl::asn1::{Asn1Object, Asn1OctetString};
+use openssl::ec::{EcGroup, EcKey};
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{HasPublic, Id, PKey, PKeyRef, Private};
+use openssl::rsa::Rsa;
+use openssl::stack::Stack;
+use openssl::x509::extension::SubjectAlternativeName;
+use openssl::x509::{X509Extension, X509Req, X509ReqBuilder};
+
+// id-pe-tlsfeature, https://www.rfc-editor.org/rfc/rfc7633#section-6
+pub const TLS_FEATURE_OID: &str = "1.3.6.1.5.5.7.1.24";
+// SEQUENCE { INTEGER 5 (status_request) }
+const TLS_FEATURE_STATUS_REQUEST: &[u8] = &[0x30, 0x03, 0x02, 0x01, 0x05];
+
+pub fn create_private_key(key_type: KeyType) -> Result<PKey<Private>> {
+    let pkey = match key_type {
+        KeyType::EcdsaP256 => ec_key(Nid::X9_62_PRIME256V1)?,
+        KeyType::EcdsaP384 => ec_key(Nid::SECP384R1)?,
+        KeyType::Rsa2048 => PKey::from_rsa(Rsa::generate(2048)?)?,
+        KeyType::Rsa4096 => PKey::from_rsa(Rsa::generate(4096)?)?,
+    };
+    Ok(pkey)
+}
+
+fn ec_key(curve: Nid) -> Result<PKey<Private>> {
+    let group = EcGroup::from_curve_name(curve)?;
+    let key = EcKey::generate(&group)?;
+    Ok(PKey::from_ec_key(key)?)
+}
+
+/// Load a csr in either pem or der format and verify its signature
+pub fn load(buf: &[u8]) -> Result<X509Req> {
+    let csr = if buf.starts_with(b"-----") {
+        X509Req::from_pem(buf)
+    } else {
+        X509Req::from_der(buf)
+    }
+    .context("Failed to parse csr")?;
+
+    let pkey = csr.public_key()?;
+    if !csr.verify(&pkey)? {
+        bail!("Signature of csr is invalid");
+    }
+    Ok(csr)
+}
+
+/// Detect the key type of a key, if it's one we would generate ourselves
+pub fn key_type<T: HasPublic>(pkey: &PKeyRef<T>) -> Option<KeyType> {
+    match (pkey.id(), pkey.bits()) {
+        (Id::EC, 256) => Some(KeyType::EcdsaP256),
+        (Id::EC, 384) => Some(KeyType::EcdsaP384),
+        (Id::RSA, 2048) => Some(KeyType::Rsa2048),
+        (Id::RSA, 4096) => Some(KeyType::Rsa4096),
+        _ => None,
+    }
+}
+
+pub fn create(pkey: &PKey<Private>, names: &[&str], must_staple: bool) -> Result<X509Req> {
+    let mut builder = X509ReqBuilder::new()?;
+    builder.set_pubkey(pkey)?;
+
+    let mut extensions = Stack::new()?;
+
+    let mut san = SubjectAlternativeName::new();
+    for name in names {
+        if let Some(addr) = config::parse_ip(name) {
+            san.ip(&addr.to_string());
+        } else {
+            san.dns(name);
+        }
+    }
+    let san = san
+        .build(&builder.x509v3_context(None))
+        .context("Failed to build subject alt names")?;
+    extensions.push(san)?;
+
+    if must_staple {
+        let oid = Asn1Object::from_str(TLS_FEATURE_OID)?;
+        let value = Asn1OctetString::new_from_bytes(TLS_FEATURE_STATUS_REQUEST)?;
+        extensions.push(X509Extension::new_from_der(&oid, false, &value)?)?;
+    }
+
+    builder.add_extensions(&extensions)?;
+    builder.sign(pkey, MessageDigest::sha256())?;
+    Ok(builder.build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn has_tls_feature(csr: &X509Req) -> bool {
+        csr.extensions().unwrap().iter().any(|ext| {
+            let der = ext.to_der().unwrap();
+            // the extension starts with the encoded oid
+            der.windows(10)
+                .any(|w| w == [0x06, 0x08, 0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x01, 0x18])
+        })
+    }
+
+    #[test]
+    fn test_csr_without_must_staple() {
+        let pkey = create_private_key(KeyType::EcdsaP384).unwrap();
+        let csr = create(&pkey, &["example.com"], false).unwrap();
+        assert!(csr.verify(&pkey).unwrap());
+        assert!(!has_tls_feature(&csr));
+    }
+
+    #[test]
+    fn test_csr_with_must_staple() {
+        let pkey = create_private_key(KeyType::EcdsaP384).unwrap();
+        let csr = create(&pkey, &["example.com", "www.example.com"], true).unwrap();
+        assert!(csr.verify(&pkey).unwrap());
+        assert!(has_tls_feature(&csr));
+    }
+
+    #[test]
+    fn test_csr_with_ip_address() {
+        let pkey = create_private_key(KeyType::EcdsaP256).unwrap();
+        let csr = create(&pkey, &["192.0.2.1", "2001:db8::1"], false).unwrap();
+        assert!(csr.verify(&pkey).unwrap());
+
+        let der = csr.to_der().unwrap();
+        // [7] iPAddress, encoded as raw octets
+        assert!(der.windows(6).any(|w| w == [0x87, 0x04, 192, 0, 2, 1]));
+        assert!(der.windows(4).any(|w| w == [0x87, 0x10, 0x20, 0x01]));
+    }
+
+    #[test]
+    fn test_create_private_key() {
+        let pkey = create_private_key(KeyType::EcdsaP256).unwrap();
+        assert_eq!(pkey.id(), Id::EC);
+        assert_eq!(pkey.bits(), 256);
+
+        let pkey = create_private_key(KeyType::Rsa2048).unwrap();
+        assert_eq!(pkey.id(), Id::RSA);
+        assert_eq!(pkey.bits(), 2048);
+
+        let csr = create(&pkey, &["example.com"], false).unwrap();
+        assert!(csr.verify(&pkey).unwrap());
+    }
+
+    #[test]
+    fn test_load_csr() {
+        let pkey = create_private_key(KeyType::EcdsaP256).unwrap();
+        let csr = create(&pkey, &["example.com"], false).unwrap();
+
+        let pem = load(&csr.to_pem().unwrap()).unwrap();
+        let der = load(&csr.to_der().unwrap()).unwrap();
+        assert_eq!(pem.to_der().unwrap(), der.to_der().unwrap());
+        let pkey = der.public_key().unwrap();
+        assert_eq!(key_type(&pkey), Some(KeyType::EcdsaP256));
+        assert!(load(b"foo").is_err());
+    }
+}
@@ -3,7 +3,10 @@ use crate::chall;
 use crate::config::{BIND_ALL_PORT_80, Config};
 use crate::errors::*;
 use crate::http_responses::*;
+use crate::metrics::{self, Counters};
+use crate::persist::FilePersist;
 use crate::sandbox;
+use crate::tls_alpn;
 use actix_web::{App, HttpServer, middleware};
 use actix_web::{HttpRequest, HttpResponse, Responder, get, web};
 use std::env;
@@ -27,8 +30,9 @@ fn not_found() -> HttpResponse {
 }
 
 #[get("/{p:.*}")]
-async fn redirect(req: HttpRequest) -> impl Responder {
+async fn redirect(req: HttpRequest, counters: web::Data<Counters>) -> impl Responder {
     debug!("REQ: {:?}", req);
+    Counters::inc(&counters.redirect_requests);
 
     let host = if let Some(host) = get_host(&req) {
         host
@@ -51,9 +55,14 @@ async fn redirect(req: HttpRequest) -> impl Responder {
 }
 
 #[get("/.well-known/acme-challenge/{chall}")]
-async fn acme(token: web::Path<String>, req: HttpRequest) -> impl Responder {
+async fn acme(
+    token: web::Path<String>,
+    req: HttpRequest,
+    counters: web::Data<Counters>,
+) -> impl Responder {
     debug!("REQ: {:?}", req);
     info!("acme: {:?}", token);
+    Counters::inc(&counters.acme_requests);
 
     if !chall::valid_token(&token) {
         return bad_request();
@@ -62,18 +71,45 @@ async fn acme(token: web::Path<String>, req: HttpRequest) -> impl Responder {
     let path = Path::new("challs").join(token.as_ref());
     debug!("Reading challenge proof: {:?}", path);
     if let Ok(proof) = fs::read(path) {
+        Counters::inc(&counters.challenge_hits);
         HttpResponse::Ok().body(proof)
     } else {
+        Counters::inc(&counters.challenge_misses);
         not_found()
     }
 }
 
+#[get("/metrics")]
+async fn prometheus(counters: web::Data<Counters>) -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics::render(&counters))
+}
+
 #[actix_web::main]
-pub async fn spawn(socket: TcpListener) -> Result<()> {
+pub async fn spawn(socket: TcpListener, metrics_socket: Option<TcpListener>) -> Result<()> {
+    let counters = web::Data::new(Counters::default());
+
+    if let Some(socket) = metrics_socket {
+        let counters = counters.clone();
+        let server =
+            HttpServer::new(move || App::new().app_data(counters.clone()).service(prometheus))
+                .workers(1)
+                .listen(socket)
+                .context("Failed to bind metrics socket")?
+                .run();
+        actix_web::rt::spawn(async {
+            if let Err(err) = server.await {
+                error!("Metrics server failed: {:#}", err);
+            }
+        });
+    }
+
     HttpServer::new(move || {
         App::new()
             // enable logger
             .wrap(middleware::Logger::default())
+            .app_data(counters.clone())
             .service(acme)
             .service(redirect)
     })
@@ -100,6 +136,40 @@ pub fn run(config: Config, args: DaemonArgs) -> Result<()> {
         .unwrap_or(BIND_ALL_PORT_80);
     let socket =
         TcpListener::bind(addr).with_context(|| anyhow!("Failed to bind socket: {addr}"))?;
+
+    let tls_socket = if let Some(addr) = args
+        .tls_bind_addr
+        .as_deref()
+        .or(config.system.tls_addr.as_deref())
+    {
+        let socket =
+            TcpListener::bind(addr).with_context(|| anyhow!("Failed to bind socket: {addr}"))?;
+        Some(socket)
+    } else {
+        None
+    };
+
+    let metrics_socket = if let Some(addr) = args
+        .metrics_bind_addr
+        .as_deref()
+        .or(config.system.metrics_addr.as_deref())
+    {
+        let socket =
+            TcpListener::bind(addr).with_context(|| anyhow!("Failed to bind socket: {addr}"))?;
+        // renew keeps this up to date, the data directory isn't readable after chroot
+        let persist = FilePersist::new(&config);
+        if let Err(err) = metrics::export(&config, &persist) {
+            warn!("Failed to export certificate metrics: {:#}", err);
+        }
+        Some(socket)
+    } else {
+        None
+    };
+
     sandbox::init(&args).context("Failed to drop privileges")?;
-    spawn(socket)
+
+    if let Some(socket) = tls_socket {
+        tls_alpn::spawn(socket)?;
+    }
+    spawn(socket, metrics_socket)
 }
@@ -1,13 +1,20 @@
+pub mod account;
 pub mod acme;
 pub mod args;
 pub mod cert;
 pub mod chall;
 pub mod check;
+pub mod client;
 pub mod config;
+pub mod csr;
 pub mod daemon;
 pub mod errors;
 pub mod http_responses;
+pub mod metrics;
+pub mod nagios;
 pub mod persist;
 pub mod renew;
+pub mod revoke;
 pub mod sandbox;
 pub mod status;
+pub mod tls_alpn;
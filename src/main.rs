@@ -1,13 +1,17 @@
-use acme_redirect::args::{self, Args, Cmd, SubCommand};
+use acme_redirect::account;
+use acme_redirect::args::{self, Args, Cmd, StatusFormat, SubCommand};
 use acme_redirect::check;
 use acme_redirect::config;
 use acme_redirect::daemon;
 use acme_redirect::errors::*;
+use acme_redirect::nagios;
 use acme_redirect::renew;
+use acme_redirect::revoke;
 use acme_redirect::status;
 use clap::Parser;
 use env_logger::Env;
 use std::io;
+use std::process;
 
 fn main() -> Result<()> {
     let args = Args::parse();
@@ -23,14 +27,22 @@ fn main() -> Result<()> {
 
     match args.subcommand.clone() {
         SubCommand::Cmds(subcommand) => {
-            let config = config::load(args)?;
+            let nagios =
+                matches!(&subcommand, Cmd::Status(status) if status.format == StatusFormat::Nagios);
+            let config = match config::load(args) {
+                Ok(config) => config,
+                Err(err) if nagios => process::exit(nagios::unknown(&err).code()),
+                Err(err) => return Err(err),
+            };
             trace!("Loaded runtime config: {:?}", config);
 
             match subcommand {
                 Cmd::Daemon(args) => daemon::run(config, args)?,
-                Cmd::Status => status::run(config)?,
+                Cmd::Status(args) => status::run(config, args)?,
                 Cmd::Renew(args) => renew::run(config, args)?,
                 Cmd::Check(args) => check::run(config, args)?,
+                Cmd::Account(args) => account::run(config, args)?,
+                Cmd::Revoke(args) => revoke::run(config, args)?,
                 Cmd::DumpConfig => {
                     serde_json::to_writer_pretty(io::stdout(), &config)?;
                     println!();
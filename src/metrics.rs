@@ -0,0 +1,201 @@
+use crate::config::Config;
+use crate::errors::*;
+use crate::persist::FilePersist;
+use crate::status::{self, CertStatus, LiveStatus};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Debug, Default)]
+pub struct Counters {
+    pub acme_requests: AtomicU64,
+    pub redirect_requests: AtomicU64,
+    pub challenge_hits: AtomicU64,
+    pub challenge_misses: AtomicU64,
+}
+
+impl Counters {
+    #[inline]
+    pub fn inc(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String) {
+        let get = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+
+        out.push_str("# HELP acme_redirect_http_requests_total Http requests by handler\n");
+        out.push_str("# TYPE acme_redirect_http_requests_total counter\n");
+        writeln!(
+            out,
+            "acme_redirect_http_requests_total{{handler=\"acme\"}} {}",
+            get(&self.acme_requests)
+        )
+        .ok();
+        writeln!(
+            out,
+            "acme_redirect_http_requests_total{{handler=\"redirect\"}} {}",
+            get(&self.redirect_requests)
+        )
+        .ok();
+
+        out.push_str(
+            "# HELP acme_redirect_challenge_requests_total Challenge requests by result\n",
+        );
+        out.push_str("# TYPE acme_redirect_challenge_requests_total counter\n");
+        writeln!(
+            out,
+            "acme_redirect_challenge_requests_total{{result=\"hit\"}} {}",
+            get(&self.challenge_hits)
+        )
+        .ok();
+        writeln!(
+            out,
+            "acme_redirect_challenge_requests_total{{result=\"miss\"}} {}",
+            get(&self.challenge_misses)
+        )
+        .ok();
+    }
+}
+
+/// The file in the challenge directory the certificate metrics are exported to
+pub const CERTS_FILE: &str = "metrics.prom";
+
+/// Export the live certificates into the challenge directory, so the daemon
+/// doesn't need access to the data directory after it dropped privileges
+pub fn export(config: &Config, persist: &FilePersist) -> Result<()> {
+    if !config.system.chall_dir.is_dir() {
+        debug!("Challenge directory doesn't exist, not exporting certificate metrics");
+        return Ok(());
+    }
+
+    let live = persist
+        .list_live_certs()
+        .context("Failed to list live certificates")?
+        .into_iter()
+        .map(|(version, name)| (name, version))
+        .collect::<HashMap<_, _>>();
+
+    let mut status = Vec::new();
+    for cert in &config.certs {
+        for variant in cert.variants(&config.acme) {
+            let name = variant.display_name(&cert.name);
+            let live = if let Some(version) = live.get(&name) {
+                let info = persist
+                    .load_version_cert_info(version)
+                    .and_then(|info| LiveStatus::new(&info, None));
+                match info {
+                    Ok(info) => Some(info),
+                    Err(err) => {
+                        warn!("Failed to load certificate {:?}: {:#}", name, err);
+                        continue;
+                    }
+                }
+            } else {
+                None
+            };
+            status.push(CertStatus {
+                name,
+                dns_names: cert.dns_names.clone(),
+                key_type: None,
+                live,
+            });
+        }
+    }
+
+    let path = config.system.chall_dir.join(CERTS_FILE);
+    let tmp = path.with_extension("prom.tmp");
+    fs::write(&tmp, status::prometheus(&status))
+        .with_context(|| anyhow!("Failed to write {:?}", tmp))?;
+    fs::rename(&tmp, &path).with_context(|| anyhow!("Failed to move metrics to {:?}", path))?;
+    Ok(())
+}
+
+/// Render the metrics, the daemon is expected to run inside of the challenge directory
+pub fn render(counters: &Counters) -> String {
+    let mut out = String::new();
+    counters.render(&mut out);
+    match fs::read_to_string(CERTS_FILE) {
+        Ok(certs) => out.push_str(&certs),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+        Err(err) => warn!("Failed to read certificate metrics: {:#}", err),
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AcmeConfig, CertConfig, SystemConfig};
+
+    #[test]
+    fn test_export() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().to_path_buf();
+        let mut config = Config {
+            certs: vec![CertConfig {
+                name: "example.com".to_string(),
+                dns_names: vec!["example.com".to_string()],
+                ..Default::default()
+            }],
+            acme: AcmeConfig::default(),
+            system: SystemConfig {
+                data_dir: path.join("data"),
+                chall_dir: path.clone(),
+                ..Default::default()
+            },
+        };
+
+        // an unreadable certificate doesn't prevent the export
+        let broken = CertConfig {
+            name: "example.org".to_string(),
+            ..config.certs[0].clone()
+        };
+        config.certs.push(broken);
+        let version = "20260515-example.org";
+        fs::create_dir_all(path.join("data/certs").join(version)).unwrap();
+        fs::write(path.join("data/certs").join(version).join("fullchain"), "").unwrap();
+        fs::create_dir_all(path.join("data/live")).unwrap();
+        std::os::unix::fs::symlink(
+            format!("../certs/{version}"),
+            path.join("data/live/example.org"),
+        )
+        .unwrap();
+
+        export(&config, &FilePersist::new(&config)).unwrap();
+        assert_eq!(
+            fs::read_to_string(path.join(CERTS_FILE)).unwrap(),
+            r#"# HELP acme_redirect_cert_live Whether a live certificate exists
+# TYPE acme_redirect_cert_live gauge
+acme_redirect_cert_live{name="example.com"} 0
+# HELP acme_redirect_cert_not_after_timestamp_seconds When the live certificate expires
+# TYPE acme_redirect_cert_not_after_timestamp_seconds gauge
+"#
+        );
+    }
+
+    #[test]
+    fn test_render_counters() {
+        let counters = Counters::default();
+        Counters::inc(&counters.acme_requests);
+        Counters::inc(&counters.challenge_hits);
+        Counters::inc(&counters.redirect_requests);
+        Counters::inc(&counters.redirect_requests);
+
+        let mut out = String::new();
+        counters.render(&mut out);
+        assert_eq!(
+            out,
+            r#"# HELP acme_redirect_http_requests_total Http requests by handler
+# TYPE acme_redirect_http_requests_total counter
+acme_redirect_http_requests_total{handler="acme"} 1
+acme_redirect_http_requests_total{handler="redirect"} 2
+# HELP acme_redirect_challenge_requests_total Challenge requests by result
+# TYPE acme_redirect_challenge_requests_total counter
+acme_redirect_challenge_requests_total{result="hit"} 1
+acme_redirect_challenge_requests_total{result="miss"} 0
+"#
+        );
+    }
+}
@@ -0,0 +1,247 @@
+use crate::args::StatusArgs;
+use crate::cert::CertInfo;
+use crate::config::{Config, RenewThreshold};
+use crate::errors::*;
+use crate::persist::FilePersist;
+use crate::status;
+use std::fmt;
+use time::OffsetDateTime;
+
+/// The plugin states, the exit code is the numeric value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum State {
+    Ok = 0,
+    Warning = 1,
+    Critical = 2,
+    Unknown = 3,
+}
+
+impl State {
+    pub fn code(&self) -> i32 {
+        *self as i32
+    }
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            State::Ok => "OK",
+            State::Warning => "WARNING",
+            State::Critical => "CRITICAL",
+            State::Unknown => "UNKNOWN",
+        };
+        write!(f, "{s}")
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct CertCheck {
+    name: String,
+    state: State,
+    message: String,
+    perfdata: Option<String>,
+}
+
+fn check_cert(
+    name: String,
+    info: Option<&CertInfo>,
+    warning: RenewThreshold,
+    critical: RenewThreshold,
+    now: OffsetDateTime,
+) -> CertCheck {
+    let Some(info) = info else {
+        return CertCheck {
+            name,
+            state: State::Critical,
+            message: "no live certificate".to_string(),
+            perfdata: None,
+        };
+    };
+
+    let left = info.expires - now;
+    let warning = warning.duration(info);
+    let critical = critical.duration(info);
+
+    let state = if left <= critical {
+        State::Critical
+    } else if left <= warning {
+        State::Warning
+    } else {
+        State::Ok
+    };
+    let message = if !left.is_positive() {
+        "expired".to_string()
+    } else if left.whole_days() > 0 {
+        format!("expires in {} days", left.whole_days())
+    } else {
+        format!("expires in {} hours", left.whole_hours())
+    };
+    let perfdata = format!(
+        "'{}'={}s;{};{};0",
+        name.replace('\'', "''"),
+        left.whole_seconds(),
+        warning.whole_seconds(),
+        critical.whole_seconds()
+    );
+
+    CertCheck {
+        name,
+        state,
+        message,
+        perfdata: Some(perfdata),
+    }
+}
+
+fn report(checks: &[CertCheck]) -> (State, String) {
+    let state = checks
+        .iter()
+        .map(|check| check.state)
+        .max()
+        .unwrap_or(State::Ok);
+
+    let problems = checks
+        .iter()
+        .filter(|check| check.state != State::Ok)
+        .map(|check| format!("{}: {}", check.name, check.message))
+        .collect::<Vec<_>>();
+    let summary = if problems.is_empty() {
+        format!("{} certificates ok", checks.len())
+    } else {
+        problems.join(", ")
+    };
+
+    let perfdata = checks
+        .iter()
+        .filter_map(|check| check.perfdata.as_deref())
+        .collect::<Vec<_>>();
+    let mut output = format!("ACME-REDIRECT {state} - {summary}");
+    if !perfdata.is_empty() {
+        output.push_str(" | ");
+        output.push_str(&perfdata.join(" "));
+    }
+
+    (state, output)
+}
+
+fn check_certs(config: &Config, args: &StatusArgs) -> Result<Vec<CertCheck>> {
+    status::check_access(config)?;
+    let persist = FilePersist::new(config);
+    let now = OffsetDateTime::now_utc();
+
+    let mut checks = Vec::new();
+    for cert in &config.certs {
+        let warning = args
+            .warning
+            .unwrap_or_else(|| cert.renew_threshold(&config.acme));
+        let critical = args.critical.unwrap_or_else(|| warning.half());
+        for variant in cert.variants(&config.acme) {
+            let info = persist.load_cert_info(&cert.name, variant.folder)?;
+            checks.push(check_cert(
+                variant.display_name(&cert.name),
+                info.as_ref(),
+                warning,
+                critical,
+                now,
+            ));
+        }
+    }
+    Ok(checks)
+}
+
+/// Print the plugin output and return the state for the exit code
+pub fn run(config: &Config, args: &StatusArgs) -> State {
+    match check_certs(config, args) {
+        Ok(checks) => {
+            let (state, output) = report(&checks);
+            println!("{output}");
+            state
+        }
+        Err(err) => unknown(&err),
+    }
+}
+
+/// Print an error that prevented the check, like an invalid config
+pub fn unknown(err: &Error) -> State {
+    // the plugin output has to be a single line without perfdata separator
+    let err = format!("{err:#}").replace('|', " ");
+    let err = err.split_whitespace().collect::<Vec<_>>().join(" ");
+    println!("ACME-REDIRECT {} - {}", State::Unknown, err);
+    State::Unknown
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+
+    fn cert_info() -> CertInfo {
+        CertInfo {
+            not_before: String::new(),
+            issued: datetime!(2026-05-07 00:00:00 UTC),
+            expires: datetime!(2026-08-05 00:00:00 UTC),
+            must_staple: false,
+            ari_id: None,
+            names: vec![],
+            issuer: String::new(),
+            serial: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_check_cert() {
+        let info = cert_info();
+        let check = |now| {
+            check_cert(
+                "example.com".to_string(),
+                Some(&info),
+                RenewThreshold::Days(30),
+                RenewThreshold::Days(15),
+                now,
+            )
+        };
+
+        let ok = check(datetime!(2026-06-01 00:00:00 UTC));
+        assert_eq!(ok.state, State::Ok);
+        assert_eq!(ok.message, "expires in 65 days");
+        assert_eq!(
+            ok.perfdata.as_deref(),
+            Some("'example.com'=5616000s;2592000;1296000;0")
+        );
+
+        let warning = check(datetime!(2026-07-15 00:00:00 UTC));
+        assert_eq!(warning.state, State::Warning);
+
+        let critical = check(datetime!(2026-08-04 12:00:00 UTC));
+        assert_eq!(critical.state, State::Critical);
+        assert_eq!(critical.message, "expires in 12 hours");
+
+        let expired = check(datetime!(2026-09-01 00:00:00 UTC));
+        assert_eq!(expired.state, State::Critical);
+        assert_eq!(expired.message, "expired");
+    }
+
+    #[test]
+    fn test_report() {
+        let info = cert_info();
+        let now = datetime!(2026-06-01 00:00:00 UTC);
+        let warning = RenewThreshold::Days(30);
+        let critical = RenewThreshold::Days(15);
+
+        let ok = check_cert("a".to_string(), Some(&info), warning, critical, now);
+        let (state, output) = report(std::slice::from_ref(&ok));
+        assert_eq!(state, State::Ok);
+        assert_eq!(
+            output,
+            "ACME-REDIRECT OK - 1 certificates ok | 'a'=5616000s;2592000;1296000;0"
+        );
+
+        let missing = check_cert("b".to_string(), None, warning, critical, now);
+        let (state, output) = report(&[ok, missing]);
+        assert_eq!(state, State::Critical);
+        assert_eq!(state.code(), 2);
+        assert_eq!(
+            output,
+            "ACME-REDIRECT CRITICAL - b: no live certificate | 'a'=5616000s;2592000;1296000;0"
+        );
+    }
+}
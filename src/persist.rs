@@ -1,17 +1,17 @@
-use crate::cert::CertInfo;
-use crate::config::Config;
+use crate::cert::{CertInfo, Certificate};
+use crate::client::ApiRenewalInfo;
+use crate::config::{Config, DEFAULT_ACCOUNT, KeyType};
 use crate::errors::*;
-use acme_micro::Certificate;
 use std::collections::HashMap;
 use std::ffi::OsStr;
 use std::fs;
 use std::fs::{DirEntry, File, OpenOptions};
 use std::io::ErrorKind;
 use std::io::prelude::*;
-use std::os::unix::fs::OpenOptionsExt;
 use std::os::unix::fs::symlink;
-use std::path::Path;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
 use std::path::PathBuf;
+use std::path::{self, Path};
 use time::OffsetDateTime;
 
 #[derive(Clone)]
@@ -26,12 +26,42 @@ impl FilePersist {
         }
     }
 
-    fn acc_privkey_path(&self) -> PathBuf {
+    fn legacy_acc_privkey_path(&self) -> PathBuf {
         self.path.join("acc.key")
     }
 
-    pub fn load_acc_privkey(&self) -> Result<Option<String>> {
-        let path = self.acc_privkey_path();
+    fn acc_privkey_path(&self, acme_url: &str, account: &str) -> PathBuf {
+        let mut path = self.path.join("accounts");
+        path.push(account_dir(acme_url));
+        path.push(format!("{account}.key"));
+        path
+    }
+
+    /// Move the account key from before accounts were stored per acme server
+    pub fn migrate_acc_privkey(&self, acme_url: &str) -> Result<()> {
+        let legacy = self.legacy_acc_privkey_path();
+        if !legacy.exists() {
+            return Ok(());
+        }
+
+        let path = self.acc_privkey_path(acme_url, DEFAULT_ACCOUNT);
+        if path.exists() {
+            warn!(
+                "Not migrating {:?}, account key already exists: {:?}",
+                legacy, path
+            );
+            return Ok(());
+        }
+
+        info!("Migrating account key {:?} to {:?}", legacy, path);
+        create_acc_dir(&path)?;
+        fs::rename(&legacy, &path)
+            .with_context(|| anyhow!("Failed to move account key to {:?}", path))?;
+        Ok(())
+    }
+
+    pub fn load_acc_privkey(&self, acme_url: &str, account: &str) -> Result<Option<String>> {
+        let path = self.acc_privkey_path(acme_url, account);
         if path.exists() {
             let buf = fs::read_to_string(&path)?;
             Ok(Some(buf))
@@ -80,11 +110,22 @@ impl FilePersist {
                 let entry = entry?;
                 let path = entry.path();
 
-                if let Some(Some(name)) = path.file_name().map(OsStr::to_str)
-                    && let Ok(link) = fs::read_link(&path)
-                    && let Some(Some(version)) = link.file_name().map(OsStr::to_str)
-                {
-                    live.insert(version.to_string(), name.to_string());
+                let Some(Some(name)) = path.file_name().map(OsStr::to_str) else {
+                    continue;
+                };
+
+                if entry.file_type()?.is_dir() {
+                    // certificate with multiple variants
+                    for entry in fs::read_dir(&path)? {
+                        let path = entry?.path();
+                        if let Some(Some(variant)) = path.file_name().map(OsStr::to_str)
+                            && let Some(version) = read_version_link(&path)
+                        {
+                            live.insert(version, format!("{name}/{variant}"));
+                        }
+                    }
+                } else if let Some(version) = read_version_link(&path) {
+                    live.insert(version, name.to_string());
                 }
             }
         }
@@ -92,10 +133,22 @@ impl FilePersist {
         Ok(live)
     }
 
-    pub fn load_cert_info(&self, name: &str) -> Result<Option<CertInfo>> {
+    pub fn live_path(&self, name: &str, folder: Option<&str>) -> PathBuf {
         let mut path = self.path.join("live");
         path.push(name);
-        path.push("fullchain");
+        if let Some(folder) = folder {
+            path.push(folder);
+        }
+        path
+    }
+
+    /// The versioned folder the live certificate points to
+    pub fn live_version_path(&self, name: &str, folder: Option<&str>) -> Option<PathBuf> {
+        fs::canonicalize(self.live_path(name, folder)).ok()
+    }
+
+    pub fn load_cert_info(&self, name: &str, folder: Option<&str>) -> Result<Option<CertInfo>> {
+        let path = self.live_path(name, folder).join("fullchain");
 
         if path.exists() {
             let buf = fs::read(&path)?;
@@ -106,17 +159,136 @@ impl FilePersist {
         }
     }
 
-    pub fn store_acc_privkey(&self, key: &str) -> Result<()> {
-        let path = self.acc_privkey_path();
+    /// Load the certificate of a versioned folder, as listed by `list_live_certs`
+    pub fn load_version_cert_info(&self, version: &str) -> Result<CertInfo> {
+        let path = self.path.join("certs").join(version).join("fullchain");
+        let buf = fs::read(&path).with_context(|| anyhow!("Failed to read {:?}", path))?;
+        CertInfo::from_pem(&buf)
+    }
+
+    /// Resolve a certificate name or versioned folder to the certificate folders
+    pub fn resolve_cert_folders(&self, target: &str) -> Result<Vec<PathBuf>> {
+        // only relative paths inside of the data directory
+        let path = Path::new(target);
+        if target.is_empty()
+            || !path
+                .components()
+                .all(|c| matches!(c, path::Component::Normal(_)))
+        {
+            bail!("Certificate name is not allowed: {:?}", target);
+        }
+
+        let live = self.path.join("live").join(target);
+        if let Ok(metadata) = fs::symlink_metadata(&live) {
+            if metadata.is_dir() {
+                // certificate with multiple variants
+                let mut folders = fs::read_dir(&live)?
+                    .map(|entry| Ok(entry?.path()))
+                    .collect::<Result<Vec<_>>>()?;
+                folders.sort();
+                return Ok(folders);
+            } else {
+                return Ok(vec![live]);
+            }
+        }
+
+        let version = target.strip_prefix("certs/").unwrap_or(target);
+        let path = self.path.join("certs").join(version);
+        if path.is_dir() {
+            return Ok(vec![path]);
+        }
+
+        bail!("No certificate found for {:?}", target)
+    }
+
+    pub fn load_key_type(&self, name: &str, folder: Option<&str>) -> Result<Option<KeyType>> {
+        let path = self.live_path(name, folder).join("key_type");
+
+        if path.exists() {
+            let buf = fs::read_to_string(&path)?;
+            let key_type = buf.trim().parse()?;
+            Ok(Some(key_type))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn load_privkey(&self, name: &str, folder: Option<&str>) -> Result<Option<String>> {
+        let path = self.live_path(name, folder).join("privkey");
+
+        if path.exists() {
+            let buf = fs::read_to_string(&path)?;
+            Ok(Some(buf))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn load_renewal_info(
+        &self,
+        name: &str,
+        folder: Option<&str>,
+    ) -> Result<Option<ApiRenewalInfo>> {
+        let path = self.live_path(name, folder).join("renewal_info");
+
+        if path.exists() {
+            let buf = fs::read(&path)?;
+            let info = serde_json::from_slice(&buf)
+                .with_context(|| anyhow!("Failed to parse renewal info: {:?}", path))?;
+            Ok(Some(info))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn store_renewal_info(
+        &self,
+        name: &str,
+        folder: Option<&str>,
+        info: &ApiRenewalInfo,
+    ) -> Result<()> {
+        let path = self.live_path(name, folder).join("renewal_info");
+        let buf = serde_json::to_vec(info)?;
+        write(&path, 0o444, &buf)
+    }
+
+    /// Write a new account key next to the current one, it's only used after `commit_acc_privkey`
+    pub fn stage_acc_privkey(&self, acme_url: &str, account: &str, key: &str) -> Result<PathBuf> {
+        let path = self
+            .acc_privkey_path(acme_url, account)
+            .with_extension("key.new");
+        create_acc_dir(&path)?;
 
+        // an existing file might be the active key after an interrupted key rollover
         let mut file = OpenOptions::new()
             .write(true)
-            .create(true)
-            .truncate(true)
+            .create_new(true)
             .mode(0o600)
-            .open(path)?;
+            .open(&path)
+            .with_context(|| anyhow!("Failed to create new account key: {:?}", path))?;
 
         file.write_all(key.as_bytes())?;
+        file.sync_all()?;
+        Ok(path)
+    }
+
+    /// Atomically replace the account key with a staged key
+    pub fn commit_acc_privkey(&self, acme_url: &str, account: &str, staged: &Path) -> Result<()> {
+        let path = self.acc_privkey_path(acme_url, account);
+        fs::rename(staged, &path)
+            .with_context(|| anyhow!("Failed to move account key to {:?}", path))?;
+        Ok(())
+    }
+
+    pub fn store_acc_privkey(&self, acme_url: &str, account: &str, key: &str) -> Result<()> {
+        let staged = self.stage_acc_privkey(acme_url, account, key)?;
+        self.commit_acc_privkey(acme_url, account, &staged)
+    }
+
+    pub fn remove_acc_privkey(&self, acme_url: &str, account: &str) -> Result<()> {
+        let path = self.acc_privkey_path(acme_url, account);
+        fs::remove_file(&path)
+            .with_context(|| anyhow!("Failed to delete account key: {:?}", path))?;
         Ok(())
     }
 
@@ -125,7 +297,12 @@ impl FilePersist {
         Ok(date)
     }
 
-    pub fn store_cert(&self, name: &str, fullcert: &Certificate) -> Result<()> {
+    pub fn store_cert(
+        &self,
+        name: &str,
+        folder: Option<&str>,
+        fullcert: &Certificate,
+    ) -> Result<()> {
         let now = Self::format_date(&OffsetDateTime::now_utc())?;
 
         let path = self.path.join("certs");
@@ -135,12 +312,15 @@ impl FilePersist {
 
         let mut i = 0;
         let path = loop {
-            let mut folder = format!("{now}-{name}");
+            let mut version = format!("{now}-{name}");
+            if let Some(folder) = folder {
+                version.push_str(&format!("-{folder}"));
+            }
             if i > 0 {
-                folder.push_str(&format!("-{i}"));
+                version.push_str(&format!("-{i}"));
             }
 
-            let path = path.join(folder);
+            let path = path.join(version);
             debug!("try atomically claiming folder: {:?}", path);
 
             let err = fs::create_dir(&path);
@@ -158,12 +338,24 @@ impl FilePersist {
         debug!("splitting chain from cert");
         let (chain, cert) = split_chain(fullcert.certificate())?;
 
-        let bundle = format!("{}{}", fullcert.private_key(), cert);
-        let full_bundle = format!("{}{}", fullcert.private_key(), fullcert.certificate());
+        if let Some(private_key) = fullcert.private_key() {
+            let bundle = format!("{private_key}{cert}");
+            let full_bundle = format!("{}{}", private_key, fullcert.certificate());
+
+            debug!("writing privkey");
+            let privkey_path = path.join("privkey");
+            write(&privkey_path, 0o440, private_key.as_bytes())?;
+
+            debug!("writing bundle");
+            let bundle_path = path.join("bundle");
+            write(&bundle_path, 0o440, bundle.as_bytes())?;
 
-        debug!("writing privkey");
-        let privkey_path = path.join("privkey");
-        write(&privkey_path, 0o440, fullcert.private_key().as_bytes())?;
+            debug!("writing full bundle with intermediates");
+            let fullbundle_path = path.join("fullbundle");
+            write(&fullbundle_path, 0o440, full_bundle.as_bytes())?;
+        } else {
+            debug!("private key is not known, skipping privkey and bundles");
+        }
 
         debug!("writing full cert with intermediates");
         let fullkey_path = path.join("fullchain");
@@ -177,23 +369,40 @@ impl FilePersist {
         let cert_path = path.join("cert");
         write(&cert_path, 0o444, cert.as_bytes())?;
 
-        debug!("writing bundle");
-        let bundle_path = path.join("bundle");
-        write(&bundle_path, 0o440, bundle.as_bytes())?;
-
-        debug!("writing full bundle with intermediates");
-        let fullbundle_path = path.join("fullbundle");
-        write(&fullbundle_path, 0o440, full_bundle.as_bytes())?;
+        if let Some(key_type) = fullcert.key_type() {
+            debug!("writing key type");
+            let key_type_path = path.join("key_type");
+            let key_type = format!("{}\n", key_type.as_str());
+            write(&key_type_path, 0o444, key_type.as_bytes())?;
+        }
 
         info!("marking cert live");
         let live = self.path.join("live");
         fs::create_dir_all(&live)
             .with_context(|| anyhow!("Failed to create folder: {:?}", &live))?;
         let live = live.join(name);
+        let is_dir = fs::symlink_metadata(&live)
+            .map(|m| m.file_type().is_dir())
+            .ok();
+        let live = if let Some(folder) = folder {
+            if is_dir == Some(false) {
+                debug!("replacing single certificate with variants folder");
+                fs::remove_file(&live).context("Failed to delete old symlink")?;
+            }
+            fs::create_dir_all(&live)
+                .with_context(|| anyhow!("Failed to create folder: {:?}", &live))?;
+            live.join(folder)
+        } else {
+            if is_dir == Some(true) {
+                debug!("replacing variants folder with single certificate");
+                fs::remove_dir_all(&live).context("Failed to delete variants folder")?;
+            }
+            live
+        };
 
         // TODO: this should be atomic (ln -sf)
         // https://github.com/coreutils/coreutils/blob/2ed7c2867974ccf7abc61c34ad7bf9565489c18e/src/force-link.c#L142-L182
-        if live.exists() {
+        if fs::symlink_metadata(&live).is_ok() {
             fs::remove_file(&live).context("Failed to delete old symlink")?;
         }
         symlink(&path, &live)
@@ -203,6 +412,35 @@ impl FilePersist {
     }
 }
 
+/// The folder name for the accounts of an acme server
+fn account_dir(acme_url: &str) -> String {
+    let url = acme_url
+        .strip_prefix("https://")
+        .or_else(|| acme_url.strip_prefix("http://"))
+        .unwrap_or(acme_url);
+    url.trim_end_matches('/')
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn create_acc_dir(key_path: &Path) -> Result<()> {
+    if let Some(parent) = key_path.parent() {
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(parent)
+            .with_context(|| anyhow!("Failed to create folder: {:?}", parent))?;
+    }
+    Ok(())
+}
+
 fn create(path: &Path, mode: u32) -> Result<File> {
     OpenOptions::new()
         .write(true)
@@ -219,6 +457,12 @@ fn write(path: &Path, mode: u32, data: &[u8]) -> Result<()> {
     Ok(())
 }
 
+fn read_version_link(path: &Path) -> Option<String> {
+    let link = fs::read_link(path).ok()?;
+    let version = link.file_name()?.to_str()?;
+    Some(version.to_string())
+}
+
 fn split_chain(fullchain: &str) -> Result<(String, String)> {
     let pems = pem::parse_many(fullchain).context("Failed to parse fullchain as pem")?;
 
@@ -617,6 +861,180 @@ aJ6TSPxS0YlSBhKYNbOUI7R8ZbjAJe/vI1IcYYhMaIW0kAzo4nxEmg==\r\n\
         );
     }
 
+    #[test]
+    fn test_store_cert_variants() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().to_path_buf();
+        let persist = FilePersist { path: path.clone() };
+        let fullchain = "-----BEGIN CERTIFICATE-----\nAAAA\n-----END CERTIFICATE-----\n";
+
+        let cert = Certificate::new(
+            KeyType::EcdsaP384,
+            "KEY\n".to_string(),
+            fullchain.to_string(),
+        );
+        persist.store_cert("example.com", None, &cert).unwrap();
+        assert_eq!(
+            persist
+                .load_privkey("example.com", None)
+                .unwrap()
+                .as_deref(),
+            Some("KEY\n")
+        );
+        assert_eq!(
+            persist.load_key_type("example.com", None).unwrap(),
+            Some(KeyType::EcdsaP384)
+        );
+
+        // switching to variants replaces the single certificate
+        let cert = Certificate::new(KeyType::Rsa2048, String::new(), fullchain.to_string());
+        persist
+            .store_cert("example.com", Some("rsa"), &cert)
+            .unwrap();
+        let cert = Certificate::new(KeyType::EcdsaP256, String::new(), fullchain.to_string());
+        persist
+            .store_cert("example.com", Some("ecdsa"), &cert)
+            .unwrap();
+
+        assert_eq!(persist.load_key_type("example.com", None).unwrap(), None);
+        assert_eq!(
+            persist.load_key_type("example.com", Some("rsa")).unwrap(),
+            Some(KeyType::Rsa2048)
+        );
+        assert_eq!(
+            persist.load_key_type("example.com", Some("ecdsa")).unwrap(),
+            Some(KeyType::EcdsaP256)
+        );
+
+        let mut live = persist
+            .list_live_certs()
+            .unwrap()
+            .into_values()
+            .collect::<Vec<_>>();
+        live.sort();
+        assert_eq!(live, &["example.com/ecdsa", "example.com/rsa"]);
+    }
+
+    #[test]
+    fn test_store_cert_without_key() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().to_path_buf();
+        let persist = FilePersist { path: path.clone() };
+        let fullchain = "-----BEGIN CERTIFICATE-----\nAAAA\n-----END CERTIFICATE-----\n";
+
+        let cert = Certificate::without_key(None, fullchain.to_string());
+        persist.store_cert("example.com", None, &cert).unwrap();
+
+        let live = persist.live_path("example.com", None);
+        assert!(live.join("fullchain").exists());
+        assert!(live.join("cert").exists());
+        assert!(!live.join("privkey").exists());
+        assert!(!live.join("bundle").exists());
+        assert!(!live.join("fullbundle").exists());
+        assert_eq!(persist.load_key_type("example.com", None).unwrap(), None);
+    }
+
+    #[test]
+    fn test_account_dir() {
+        assert_eq!(
+            account_dir("https://acme-v02.api.letsencrypt.org/directory"),
+            "acme-v02.api.letsencrypt.org_directory"
+        );
+        assert_eq!(
+            account_dir("https://ca.example.com:9000/acme/acme/directory/"),
+            "ca.example.com_9000_acme_acme_directory"
+        );
+    }
+
+    #[test]
+    fn test_migrate_acc_privkey() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().to_path_buf();
+        let persist = FilePersist { path: path.clone() };
+        let url = "https://acme.example.com/directory";
+
+        fs::write(path.join("acc.key"), "KEY").unwrap();
+        persist.migrate_acc_privkey(url).unwrap();
+        assert!(!path.join("acc.key").exists());
+        assert_eq!(
+            persist.load_acc_privkey(url, DEFAULT_ACCOUNT).unwrap(),
+            Some("KEY".to_string())
+        );
+        assert_eq!(persist.load_acc_privkey(url, "other").unwrap(), None);
+        assert_eq!(
+            persist
+                .load_acc_privkey(
+                    "https://acme-staging.example.com/directory",
+                    DEFAULT_ACCOUNT
+                )
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_resolve_cert_folders() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().to_path_buf();
+        let persist = FilePersist { path: path.clone() };
+        fs::create_dir_all(path.join("certs/20260515-example.com")).unwrap();
+
+        assert_eq!(
+            persist
+                .resolve_cert_folders("certs/20260515-example.com")
+                .unwrap(),
+            [path.join("certs/20260515-example.com")]
+        );
+        for target in ["", "..", "certs/../..", "/etc", "/", "./certs"] {
+            let err = persist.resolve_cert_folders(target).unwrap_err();
+            assert!(
+                err.to_string()
+                    .starts_with("Certificate name is not allowed"),
+                "{target:?} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn test_stage_acc_privkey() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().to_path_buf();
+        let persist = FilePersist { path: path.clone() };
+        let url = "https://acme.example.com/directory";
+
+        persist
+            .store_acc_privkey(url, DEFAULT_ACCOUNT, "OLD")
+            .unwrap();
+        let staged = persist
+            .stage_acc_privkey(url, DEFAULT_ACCOUNT, "NEW")
+            .unwrap();
+        let mode = fs::metadata(&staged).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(
+            persist.load_acc_privkey(url, DEFAULT_ACCOUNT).unwrap(),
+            Some("OLD".to_string())
+        );
+
+        // a leftover key is never overwritten
+        assert!(
+            persist
+                .stage_acc_privkey(url, DEFAULT_ACCOUNT, "OTHER")
+                .is_err()
+        );
+        assert_eq!(fs::read_to_string(&staged).unwrap(), "NEW");
+
+        persist
+            .commit_acc_privkey(url, DEFAULT_ACCOUNT, &staged)
+            .unwrap();
+        assert!(!staged.exists());
+        assert_eq!(
+            persist.load_acc_privkey(url, DEFAULT_ACCOUNT).unwrap(),
+            Some("NEW".to_string())
+        );
+    }
+
     #[test]
     fn test_format_datetime() {
         let date = FilePersist::format_date(&time::macros::datetime!(
@@ -1,104 +1,709 @@
 use crate::acme;
 use crate::args::RenewArgs;
-use crate::chall::Challenge;
+use crate::cert::CertInfo;
+use crate::chall;
+use crate::client::{ApiRenewalInfo, Directory, ExternalAccountBinding};
 use crate::config::CertConfig;
+use crate::config::CertVariant;
 use crate::config::Config;
+use crate::config::{Hook, HookCmd, HookFailMode, HookOptions};
+use crate::csr;
 use crate::errors::*;
+use crate::metrics;
 use crate::persist::FilePersist;
-use std::collections::HashSet;
+use colored::Colorize;
+use nix::sys::signal::{self, Signal};
+use nix::unistd::Pid;
+use openssl::pkey::PKey;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
 use std::fs;
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
 use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+
+const HOOK_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const HOOK_RETRY_DELAY: Duration = Duration::from_secs(1);
+/// Query the suggested renewal window, falls back to the last known window
+///
+/// The directory is only fetched once and shared by all variants.
+fn renewal_info(
+    args: &RenewArgs,
+    config: &Config,
+    persist: &FilePersist,
+    cert: &CertConfig,
+    variant: &CertVariant,
+    existing: &CertInfo,
+    directory: &mut Option<Directory>,
+) -> Option<ApiRenewalInfo> {
+    let name = variant.display_name(&cert.name);
+    let cert_id = existing.ari_id.as_ref()?;
+
+    let info = match directory {
+        Some(dir) => dir.renewal_info(cert_id),
+        None => Directory::from_url(cert.acme_url(&config.acme))
+            .and_then(|dir| directory.insert(dir).renewal_info(cert_id)),
+    };
+    match info {
+        Ok(Some(info)) => {
+            debug!("{:?}: suggested renewal window: {:?}", name, info);
+            if !args.dry_run
+                && let Err(err) = persist.store_renewal_info(&cert.name, variant.folder, &info)
+            {
+                warn!("{:?}: Failed to store renewal info: {:#}", name, err);
+            }
+            Some(info)
+        }
+        Ok(None) => None,
+        Err(err) => {
+            warn!("{:?}: Failed to fetch renewal info: {:#}", name, err);
+            persist
+                .load_renewal_info(&cert.name, variant.folder)
+                .unwrap_or_else(|err| {
+                    warn!("{:?}: Failed to load renewal info: {:#}", name, err);
+                    None
+                })
+        }
+    }
+}
 
 fn should_request_cert(
     args: &RenewArgs,
     config: &Config,
     persist: &FilePersist,
     cert: &CertConfig,
+    variant: &CertVariant,
+    directory: &mut Option<Directory>,
 ) -> Result<bool> {
+    let name = variant.display_name(&cert.name);
     if args.force_renew {
-        info!("{:?}: force renewing", cert.name);
+        info!("{:?}: force renewing", name);
         Ok(true)
-    } else if let Some(existing) = persist.load_cert_info(&cert.name)? {
-        let days_left = existing.days_left();
-        if days_left <= config.acme.renew_if_days_left {
-            info!("{:?}: existing cert is below threshold", cert.name);
+    } else if let Some(existing) = persist.load_cert_info(&cert.name, variant.folder)? {
+        let drift = existing.name_drift(&cert.names());
+        if !drift.is_empty() {
+            info!("{:?}: names of existing cert differ ({})", name, drift);
+            return Ok(true);
+        }
+
+        let renew_at = renewal_info(args, config, persist, cert, variant, &existing, directory)
+            .and_then(|info| info.suggested_window.random_time().ok());
+        if let Some(renew_at) = renew_at
+            && renew_at <= OffsetDateTime::now_utc()
+        {
+            info!("{:?}: inside of suggested renewal window", name);
+            Ok(true)
+        } else if cert
+            .renew_threshold(&config.acme)
+            .should_renew(&existing, OffsetDateTime::now_utc())
+        {
+            info!("{:?}: existing cert is below threshold", name);
             Ok(true)
         } else {
-            info!("{:?}: cert already satisfied", cert.name);
+            info!("{:?}: cert already satisfied", name);
             Ok(false)
         }
     } else {
-        info!("{:?}: creating new cert", cert.name);
+        info!("{:?}: creating new cert", name);
         Ok(true)
     }
 }
 
-fn execute_hooks(hooks: &[String], dry_run: bool) -> Result<()> {
-    for exec in hooks {
+fn load_reusable_key(
+    persist: &FilePersist,
+    cert: &CertConfig,
+    variant: &CertVariant,
+) -> Result<Option<String>> {
+    let name = variant.display_name(&cert.name);
+    let Some(key) = persist.load_privkey(&cert.name, variant.folder)? else {
+        info!("{:?}: no existing key found, generating new key", name);
+        return Ok(None);
+    };
+    let key_type = if let Some(key_type) = persist.load_key_type(&cert.name, variant.folder)? {
+        Some(key_type)
+    } else {
+        // certificates of older versions don't have a key_type file
+        let pkey = PKey::private_key_from_pem(key.as_bytes())
+            .with_context(|| anyhow!("{:?}: Failed to parse existing key", name))?;
+        csr::key_type(&pkey)
+    };
+    if key_type != Some(variant.key_type) {
+        info!("{:?}: key type has changed, generating new key", name);
+        return Ok(None);
+    }
+    Ok(Some(key))
+}
+
+fn hook_command(hook: &Hook) -> Result<Command> {
+    let mut cmd = match &hook.cmd {
+        HookCmd::Shell(exec) => {
+            let mut cmd = Command::new("sh");
+            cmd.arg("-c").arg(exec);
+            cmd
+        }
+        HookCmd::Argv(argv) => {
+            let (program, args) = argv
+                .split_first()
+                .context("Hook command must not be empty")?;
+            let mut cmd = Command::new(program);
+            cmd.args(args);
+            cmd
+        }
+    };
+
+    if let Some(name) = &hook.user {
+        let user = uzers::get_user_by_name(name)
+            .ok_or_else(|| anyhow!("Failed to look up user: {:?}", name))?;
+        // supplementary groups are cleared by std when dropping from root
+        cmd.uid(user.uid()).gid(user.primary_group_id());
+    }
+    if let Some(path) = &hook.working_dir {
+        cmd.current_dir(path);
+    }
+    cmd.envs(&hook.env);
+    Ok(cmd)
+}
+
+/// Run a hook once, it's killed with all its children if it doesn't finish in time
+fn spawn_hook(hook: &Hook, env: &[(&str, String)], timeout: Option<Duration>) -> Result<()> {
+    let mut cmd = hook_command(hook)?;
+    cmd.envs(env.iter().map(|(key, value)| (key, value)));
+    if timeout.is_some() {
+        cmd.process_group(0);
+    }
+    let mut child = cmd.spawn().context("Failed to spawn hook")?;
+
+    let status = if let Some(timeout) = timeout {
+        let started = Instant::now();
+        loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if started.elapsed() >= timeout {
+                let pgid = Pid::from_raw(child.id() as i32);
+                if let Err(err) = signal::killpg(pgid, Signal::SIGKILL) {
+                    debug!("Failed to kill process group: {:#}", err);
+                    child.kill().ok();
+                }
+                child.wait()?;
+                bail!("Timed out after {}s", timeout.as_secs());
+            }
+            thread::sleep(HOOK_POLL_INTERVAL);
+        }
+    } else {
+        child.wait()?
+    };
+
+    if !status.success() {
+        bail!("Exited with {}", status);
+    }
+    Ok(())
+}
+
+fn execute_hook(hook: &Hook, env: &[(&str, String)], options: &HookOptions) -> Result<()> {
+    let mut attempt = 0;
+    loop {
+        match spawn_hook(hook, env, options.timeout) {
+            Ok(()) => return Ok(()),
+            Err(err) if attempt < options.retries => {
+                attempt += 1;
+                info!(
+                    "Hook {} failed, retrying ({}/{}): {:#}",
+                    hook, attempt, options.retries, err
+                );
+                thread::sleep(HOOK_RETRY_DELAY);
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+pub fn execute_hooks(
+    hooks: &[Hook],
+    env: &[(&str, String)],
+    options: &HookOptions,
+    dry_run: bool,
+) -> Result<()> {
+    let mut failed = Vec::new();
+    for hook in hooks {
         if dry_run {
-            info!("executing hook: {:?} (dry run)", exec);
+            info!("executing hook: {} (dry run)", hook);
         } else {
-            info!("executing hook: {:?}", exec);
+            info!("executing hook: {}", hook);
+            if let Err(err) = execute_hook(hook, env, options) {
+                failed.push(format!("{hook} ({err:#})"));
+            }
+        }
+    }
+
+    if !failed.is_empty() {
+        let err = anyhow!("Failed to execute hooks: {}", failed.join(", "));
+        match options.fail_mode {
+            HookFailMode::Fail => return Err(err),
+            HookFailMode::Warn => warn!("{:#}", err),
+            HookFailMode::Ignore => debug!("Ignoring failure: {:#}", err),
+        }
+    }
+    Ok(())
+}
 
-            let status = Command::new("sh")
-                .arg("-c")
-                .arg(exec)
-                .status()
-                .context("Failed to spawn shell for hook")?;
+/// The certificate folders of a renewal, passed to the hooks
+#[derive(Debug, Default)]
+pub struct Renewal {
+    pub cert_dirs: Vec<PathBuf>,
+    pub previous_cert_dirs: Vec<PathBuf>,
+    pub not_after: Option<OffsetDateTime>,
+    /// The certificate was renewed but the post_exec hooks failed
+    pub post_exec_error: Option<Error>,
+}
+
+impl Renewal {
+    fn add(
+        &mut self,
+        persist: &FilePersist,
+        cert: &CertConfig,
+        variant: &CertVariant,
+    ) -> Result<()> {
+        if let Some(path) = persist.live_version_path(&cert.name, variant.folder) {
+            self.cert_dirs.push(path);
+        }
+        if let Some(info) = persist.load_cert_info(&cert.name, variant.folder)? {
+            self.not_after = Some(match self.not_after {
+                Some(not_after) => not_after.min(info.expires),
+                None => info.expires,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Execute the global hooks followed by the hooks of the certificate
+fn execute_cert_hooks(
+    config: &Config,
+    cert: &CertConfig,
+    global: &[Hook],
+    hooks: &[Hook],
+    env: &[(&str, String)],
+    dry_run: bool,
+) -> Result<()> {
+    let result = execute_hooks(global, env, &config.system.hook_options(), dry_run);
+    let cert = execute_hooks(hooks, env, &cert.hook_options(&config.system), dry_run);
+    result.and(cert)
+}
 
-            if !status.success() {
-                error!("Failed to execute hook: {:?}", exec);
+fn request_certs(
+    args: &RenewArgs,
+    config: &Config,
+    persist: &FilePersist,
+    cert: &CertConfig,
+    eab: Option<&ExternalAccountBinding>,
+    variants: &[CertVariant],
+) -> Result<Renewal> {
+    // the external account binding is only valid for the global acme_url
+    let eab = eab.filter(|_| cert.acme_url(&config.acme) == config.acme.acme_url);
+    let mut renewal = Renewal::default();
+    if args.dry_run || args.hooks_only {
+        for variant in variants {
+            info!("renewing {:?} (dry run)", variant.display_name(&cert.name));
+            renewal.add(persist, cert, variant)?;
+        }
+    } else {
+        let mut solver = chall::solver_for(config, cert)?;
+        // hooks are only executed once all variants have been renewed
+        let result = variants.iter().try_for_each(|variant| {
+            let name = variant.display_name(&cert.name);
+            info!("renewing {:?}", name);
+            let private_key = if cert.reuse_key && !args.new_key {
+                load_reusable_key(persist, cert, variant)?
+            } else {
+                None
+            };
+            let replaces = persist
+                .load_cert_info(&cert.name, variant.folder)?
+                .and_then(|existing| existing.ari_id);
+            if let Some(path) = persist.live_version_path(&cert.name, variant.folder) {
+                renewal.previous_cert_dirs.push(path);
             }
+            let csr = if let Some(path) = &cert.csr {
+                let csr = fs::read(path)
+                    .with_context(|| anyhow!("Failed to read csr from {:?}", path))?;
+                Some(csr)
+            } else {
+                None
+            };
+            acme::request(
+                persist.clone(),
+                solver.as_mut(),
+                &acme::Request {
+                    account_email: config.acme.acme_email.as_deref(),
+                    acme_url: cert.acme_url(&config.acme),
+                    account: cert.account(),
+                    primary_name: &cert.name,
+                    alt_names: &cert.dns_names,
+                    must_staple: cert.must_staple,
+                    key_type: variant.key_type,
+                    folder: variant.folder,
+                    private_key: private_key.as_deref(),
+                    csr: csr.as_deref(),
+                    eab,
+                    replaces: replaces.as_deref(),
+                    profile: cert.profile(&config.acme),
+                },
+            )
+            .with_context(|| anyhow!("Fail to get certificate {:?}", name))?;
+            renewal.add(persist, cert, variant)
+        });
+        let cleanup = solver.cleanup().context("Failed to clean up challenges");
+        // the request error is more relevant, but don't lose the cleanup error
+        if let (Err(_), Err(err)) = (&result, &cleanup) {
+            error!("{:#}", err);
         }
+        result?;
+        cleanup?;
     }
-    Ok(())
+    Ok(renewal)
 }
 
+/// Request new certificates if needed, returns None if nothing had to be renewed
 fn renew_cert(
     args: &RenewArgs,
     config: &Config,
     persist: &FilePersist,
     cert: &CertConfig,
-) -> Result<()> {
-    let mut challenge = Challenge::new(config);
-
-    if !should_request_cert(args, config, persist, cert)? {
+    eab: Option<&ExternalAccountBinding>,
+) -> Result<Option<Renewal>> {
+    let mut variants = Vec::new();
+    let mut directory = None;
+    for variant in cert.variants(&config.acme) {
+        if should_request_cert(args, config, persist, cert, &variant, &mut directory)? {
+            variants.push(variant);
+        }
+    }
+    if variants.is_empty() {
         debug!("Not requesting a certificate for {:?}", cert.name);
-        return Ok(());
+        return Ok(None);
     }
 
-    if args.dry_run || args.hooks_only {
-        info!("renewing {:?} (dry run)", cert.name);
-    } else {
-        info!("renewing {:?}", cert.name);
-        acme::request(
-            persist.clone(),
-            &mut challenge,
-            &acme::Request {
-                account_email: config.acme.acme_email.as_deref(),
-                acme_url: &config.acme.acme_url,
-                primary_name: &cert.name,
-                alt_names: &cert.dns_names,
-            },
-        )
-        .with_context(|| anyhow!("Fail to get certificate {:?}", cert.name))?;
-        challenge.cleanup()?;
+    // nothing is requested, so there's nothing to prepare or clean up
+    if args.hooks_only {
+        return request_certs(args, config, persist, cert, eab, &variants).map(Some);
+    }
+
+    let mut env = vec![
+        ("ACME_CERT_NAME", cert.name.clone()),
+        ("ACME_DNS_NAMES", cert.dns_names.join(" ")),
+        ("ACME_DRY_RUN", (args.dry_run as u8).to_string()),
+    ];
+
+    debug!("Executing pre_exec hooks");
+    let result = execute_cert_hooks(
+        config,
+        cert,
+        &config.system.pre_exec,
+        &cert.pre_exec,
+        &env,
+        args.dry_run,
+    )
+    .context("Failed to execute pre_exec hooks")
+    .and_then(|()| request_certs(args, config, persist, cert, eab, &variants));
+
+    if let Err(err) = &result {
+        env.push(("ACME_ERROR", format!("{err:#}")));
+        debug!("Executing on_failure hooks");
+        if let Err(err) = execute_cert_hooks(
+            config,
+            cert,
+            &config.system.on_failure,
+            &cert.on_failure,
+            &env,
+            args.dry_run,
+        ) {
+            error!(
+                "Failed to execute on_failure hooks ({:?}): {:#}",
+                cert.name, err
+            );
+        }
+    }
+
+    debug!("Executing post_exec hooks");
+    let post = execute_cert_hooks(
+        config,
+        cert,
+        &config.system.post_exec,
+        &cert.post_exec,
+        &env,
+        args.dry_run,
+    )
+    .context("Failed to execute post_exec hooks");
+
+    match (result, post) {
+        (Ok(mut renewal), post) => {
+            renewal.post_exec_error = post.err();
+            Ok(Some(renewal))
+        }
+        (Err(err), Ok(())) => Err(err),
+        (Err(err), Err(post)) => {
+            error!("{:?}: {:#}", cert.name, post);
+            Err(err)
+        }
+    }
+}
+
+fn join_paths(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|path| path.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The environment variables that describe the renewed certificate to the hooks
+fn hook_env(
+    args: &RenewArgs,
+    persist: &FilePersist,
+    cert: &CertConfig,
+    renewal: &Renewal,
+) -> Result<Vec<(&'static str, String)>> {
+    let mut env = vec![
+        ("ACME_CERT_NAME", cert.name.clone()),
+        ("ACME_CERT_DIR", join_paths(&renewal.cert_dirs)),
+        (
+            "ACME_LIVE_DIR",
+            persist.live_path(&cert.name, None).to_string_lossy().into(),
+        ),
+        ("ACME_DNS_NAMES", cert.dns_names.join(" ")),
+        (
+            "ACME_DRY_RUN",
+            ((args.dry_run || args.hooks_only) as u8).to_string(),
+        ),
+    ];
+    if !renewal.previous_cert_dirs.is_empty() {
+        env.push((
+            "ACME_PREVIOUS_CERT_DIR",
+            join_paths(&renewal.previous_cert_dirs),
+        ));
+    }
+    if let Some(not_after) = renewal.not_after {
+        env.push(("ACME_NOT_AFTER", not_after.format(&Rfc3339)?));
+    }
+    Ok(env)
+}
+
+/// A hook that is executed once after all certificates have been processed
+#[derive(Debug, PartialEq)]
+struct DeferredHook {
+    hook: Hook,
+    options: HookOptions,
+    /// The indexes of the certificates that requested this hook
+    certs: Vec<usize>,
+}
+
+/// The hooks of all renewed certificates, each distinct command only runs once
+#[derive(Debug, Default)]
+struct DeferredHooks {
+    hooks: Vec<DeferredHook>,
+    /// The exec_extra hooks, executed after all other hooks
+    extra: Vec<DeferredHook>,
+    /// The hook environment of each renewed certificate
+    envs: HashMap<usize, Vec<(&'static str, String)>>,
+}
+
+impl DeferredHooks {
+    fn add_cert(&mut self, cert: usize, env: Vec<(&'static str, String)>) {
+        self.envs.insert(cert, env);
+    }
+
+    fn add(&mut self, hooks: &[Hook], options: HookOptions, cert: usize) {
+        for hook in hooks {
+            // a hook that is also used by exec_extra still only runs once
+            if let Some(idx) = self.extra.iter().position(|extra| extra.hook == *hook) {
+                let extra = self.extra.remove(idx);
+                self.hooks.push(extra);
+            }
+            Self::insert(&mut self.hooks, hook, options, cert);
+        }
+    }
+
+    fn add_extra(&mut self, hooks: &[Hook], options: HookOptions, cert: usize) {
+        for hook in hooks {
+            if self.hooks.iter().any(|deferred| deferred.hook == *hook) {
+                Self::insert(&mut self.hooks, hook, options, cert);
+            } else {
+                Self::insert(&mut self.extra, hook, options, cert);
+            }
+        }
     }
 
-    if !args.skip_restarts {
-        if !cert.exec.is_empty() {
-            debug!("Executing hooks for this certificate");
-            execute_hooks(&cert.exec, args.dry_run)?;
+    fn insert(list: &mut Vec<DeferredHook>, hook: &Hook, options: HookOptions, cert: usize) {
+        if let Some(deferred) = list.iter_mut().find(|deferred| deferred.hook == *hook) {
+            deferred.options = deferred.options.merge(options);
+            if !deferred.certs.contains(&cert) {
+                deferred.certs.push(cert);
+            }
         } else {
-            debug!("Executing global default hooks");
-            execute_hooks(&config.system.exec, args.dry_run)?;
+            list.push(DeferredHook {
+                hook: hook.clone(),
+                options,
+                certs: vec![cert],
+            });
         }
+    }
+
+    /// A hook of a single certificate gets its environment, the lists of all
+    /// certificates are always set
+    fn hook_env(&self, args: &RenewArgs, hook: &DeferredHook) -> Vec<(&'static str, String)> {
+        let envs = hook
+            .certs
+            .iter()
+            .filter_map(|idx| self.envs.get(idx))
+            .collect::<Vec<_>>();
+        let join = |key| {
+            envs.iter()
+                .filter_map(|env| env.iter().find(|(k, _)| *k == key))
+                .map(|(_, value)| value.as_str())
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
 
-        debug!("Executing global exec_extra hooks");
-        execute_hooks(&config.system.exec_extra, args.dry_run)?;
+        let mut env = if let [cert] = envs.as_slice() {
+            cert.to_vec()
+        } else {
+            vec![(
+                "ACME_DRY_RUN",
+                ((args.dry_run || args.hooks_only) as u8).to_string(),
+            )]
+        };
+        env.push(("ACME_CERT_NAMES", join("ACME_CERT_NAME")));
+        env.push(("ACME_CERT_DIRS", join("ACME_CERT_DIR")));
+        env.push(("ACME_LIVE_DIRS", join("ACME_LIVE_DIR")));
+        env
     }
 
-    Ok(())
+    /// Execute the hooks and mark the certificates of failed hooks
+    fn run(&self, args: &RenewArgs, certs: &mut [CertReport]) {
+        for hook in self.hooks.iter().chain(&self.extra) {
+            let env = self.hook_env(args, hook);
+            let hooks = std::slice::from_ref(&hook.hook);
+            if let Err(err) = execute_hooks(hooks, &env, &hook.options, args.dry_run) {
+                let names = hook
+                    .certs
+                    .iter()
+                    .map(|idx| certs[*idx].name.as_str())
+                    .collect::<Vec<_>>();
+                error!("Failed to execute hooks ({:?}): {:#}", names, err);
+                for idx in &hook.certs {
+                    let cert = &mut certs[*idx];
+                    cert.outcome = Outcome::HookFailed;
+                    cert.error.get_or_insert_with(|| format!("{err:#}"));
+                }
+            }
+        }
+    }
+}
+
+/// Execute the per-cert hooks and defer everything else to the end of the run
+fn run_hooks(
+    args: &RenewArgs,
+    config: &Config,
+    persist: &FilePersist,
+    cert: &CertConfig,
+    renewal: &Renewal,
+    deferred: &mut DeferredHooks,
+    idx: usize,
+) -> Result<()> {
+    if args.skip_restarts {
+        return Ok(());
+    }
+
+    let global = config.system.hook_options();
+    let env = hook_env(args, persist, cert, renewal)?;
+
+    let result = if cert.exec.is_empty() {
+        debug!("Deferring global default hooks");
+        deferred.add(&config.system.exec, global, idx);
+        Ok(())
+    } else if cert.exec_per_cert {
+        debug!("Executing hooks for this certificate");
+        let options = cert.hook_options(&config.system);
+        execute_hooks(&cert.exec, &env, &options, args.dry_run)
+    } else {
+        debug!("Deferring hooks for this certificate");
+        deferred.add(&cert.exec, cert.hook_options(&config.system), idx);
+        Ok(())
+    };
+
+    debug!("Deferring global exec_extra hooks");
+    deferred.add_extra(&config.system.exec_extra, global, idx);
+    deferred.add_cert(idx, env);
+    result
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Outcome {
+    Skipped,
+    Renewed,
+    Failed,
+    HookFailed,
+}
+
+impl Outcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Skipped => "skipped",
+            Outcome::Renewed => "renewed",
+            Outcome::Failed => "failed",
+            Outcome::HookFailed => "hook-failed",
+        }
+    }
+
+    pub fn is_failure(&self) -> bool {
+        matches!(self, Outcome::Failed | Outcome::HookFailed)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CertReport {
+    pub name: String,
+    pub outcome: Outcome,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub finished: String,
+    pub dry_run: bool,
+    pub certs: Vec<CertReport>,
+}
+
+impl Report {
+    pub fn failed(&self) -> usize {
+        self.certs
+            .iter()
+            .filter(|cert| cert.outcome.is_failure())
+            .count()
+    }
+
+    fn summary(&self) -> String {
+        let mut out = String::new();
+        for cert in &self.certs {
+            let outcome = match cert.outcome {
+                Outcome::Skipped => cert.outcome.as_str().normal(),
+                Outcome::Renewed => cert.outcome.as_str().green(),
+                Outcome::Failed | Outcome::HookFailed => cert.outcome.as_str().red(),
+            };
+            out += &format!("{:50} {}", cert.name.bold(), outcome);
+            if let Some(error) = &cert.error {
+                out += &format!(": {error}");
+            }
+            out.push('\n');
+        }
+        out
+    }
 }
 
 fn cleanup_certs(persist: &FilePersist, dry_run: bool) -> Result<()> {
@@ -141,14 +746,466 @@ fn cleanup_certs(persist: &FilePersist, dry_run: bool) -> Result<()> {
 pub fn run(config: Config, mut args: RenewArgs) -> Result<()> {
     let persist = FilePersist::new(&config);
 
+    if !args.dry_run {
+        persist
+            .migrate_acc_privkey(&config.acme.acme_url)
+            .context("Failed to migrate account key")?;
+    }
+    let eab = acme::load_eab(&config.acme).context("Invalid external account binding")?;
+
+    let mut certs = Vec::new();
+    let mut deferred = DeferredHooks::default();
     let filter = args.certs.drain(..).collect::<HashSet<_>>();
     for cert in config.filter_certs(&filter) {
-        if let Err(err) = renew_cert(&args, &config, &persist, cert) {
-            error!("Failed to renew ({:?}): {:#}", cert.name, err);
-        }
+        let idx = certs.len();
+        let (outcome, error) = match renew_cert(&args, &config, &persist, cert, eab.as_ref()) {
+            Ok(None) => (Outcome::Skipped, None),
+            Ok(Some(mut renewal)) => {
+                let post_exec = renewal.post_exec_error.take().map_or(Ok(()), Err);
+                match run_hooks(&args, &config, &persist, cert, &renewal, &mut deferred, idx)
+                    .and(post_exec)
+                {
+                    Ok(()) => (Outcome::Renewed, None),
+                    Err(err) => {
+                        error!("Failed to execute hooks ({:?}): {:#}", cert.name, err);
+                        (Outcome::HookFailed, Some(format!("{err:#}")))
+                    }
+                }
+            }
+            Err(err) => {
+                error!("Failed to renew ({:?}): {:#}", cert.name, err);
+                (Outcome::Failed, Some(format!("{err:#}")))
+            }
+        };
+        certs.push(CertReport {
+            name: cert.name.clone(),
+            outcome,
+            error,
+        });
+    }
+
+    debug!("Executing deferred hooks");
+    deferred.run(&args, &mut certs);
+
+    if !args.dry_run
+        && let Err(err) = metrics::export(&config, &persist)
+    {
+        warn!("Failed to export certificate metrics: {:#}", err);
     }
 
+    let report = Report {
+        finished: OffsetDateTime::now_utc().format(&Rfc3339)?,
+        dry_run: args.dry_run,
+        certs,
+    };
+    // only print the summary if something happened, to keep cron quiet
+    if report
+        .certs
+        .iter()
+        .any(|cert| cert.outcome != Outcome::Skipped)
+    {
+        print!("{}", report.summary());
+    }
+    if let Some(path) = &args.report {
+        let json = serde_json::to_string_pretty(&report)?;
+        fs::write(path, json + "\n")
+            .with_context(|| anyhow!("Failed to write report to {:?}", path))?;
+    }
+
+    // the outcome of each certificate has been reported already
     cleanup_certs(&persist, args.dry_run).context("Failed to cleanup old certs")?;
 
+    let failed = report.failed();
+    if failed > 0 {
+        bail!(
+            "Failed to renew {} of {} certificates",
+            failed,
+            report.certs.len()
+        );
+    }
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AcmeConfig, KeyType, SystemConfig};
+    use clap::Parser;
+
+    fn cert_config() -> CertConfig {
+        CertConfig {
+            name: "example.com".to_string(),
+            dns_names: vec!["example.com".to_string(), "www.example.com".to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_hook_env() {
+        let config = Config {
+            certs: vec![],
+            acme: AcmeConfig::default(),
+            system: SystemConfig {
+                data_dir: PathBuf::from("/var/lib/acme-redirect"),
+                ..Default::default()
+            },
+        };
+        let persist = FilePersist::new(&config);
+        let cert = cert_config();
+        let args = RenewArgs::parse_from(["renew", "--dry-run"]);
+        let renewal = Renewal {
+            cert_dirs: vec![PathBuf::from(
+                "/var/lib/acme-redirect/certs/20260515-example.com",
+            )],
+            previous_cert_dirs: vec![],
+            not_after: Some(time::macros::datetime!(2026-08-13 16:20:00 UTC)),
+            post_exec_error: None,
+        };
+
+        let env = hook_env(&args, &persist, &cert, &renewal).unwrap();
+        assert_eq!(
+            env,
+            [
+                ("ACME_CERT_NAME", "example.com".to_string()),
+                (
+                    "ACME_CERT_DIR",
+                    "/var/lib/acme-redirect/certs/20260515-example.com".to_string()
+                ),
+                (
+                    "ACME_LIVE_DIR",
+                    "/var/lib/acme-redirect/live/example.com".to_string()
+                ),
+                ("ACME_DNS_NAMES", "example.com www.example.com".to_string()),
+                ("ACME_DRY_RUN", "1".to_string()),
+                ("ACME_NOT_AFTER", "2026-08-13T16:20:00Z".to_string()),
+            ]
+        );
+
+        execute_hooks(
+            &[r#"test "$ACME_DNS_NAMES" = "example.com www.example.com""#.into()],
+            &env,
+            &config.system.hook_options(),
+            false,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_reusable_key_without_key_type() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_path_buf();
+        let config = Config {
+            certs: vec![],
+            acme: AcmeConfig::default(),
+            system: SystemConfig {
+                data_dir: dir.clone(),
+                ..Default::default()
+            },
+        };
+        let persist = FilePersist::new(&config);
+        let cert = cert_config();
+        let variant = cert.variants(&config.acme).remove(0);
+
+        assert_eq!(load_reusable_key(&persist, &cert, &variant).unwrap(), None);
+
+        let live = persist.live_path(&cert.name, None);
+        fs::create_dir_all(&live).unwrap();
+        let pkey = csr::create_private_key(variant.key_type).unwrap();
+        let pem = String::from_utf8(pkey.private_key_to_pem_pkcs8().unwrap()).unwrap();
+        fs::write(live.join("privkey"), &pem).unwrap();
+        assert_eq!(
+            load_reusable_key(&persist, &cert, &variant).unwrap(),
+            Some(pem)
+        );
+
+        let pkey = csr::create_private_key(KeyType::Rsa2048).unwrap();
+        let pem = String::from_utf8(pkey.private_key_to_pem_pkcs8().unwrap()).unwrap();
+        fs::write(live.join("privkey"), &pem).unwrap();
+        assert_eq!(load_reusable_key(&persist, &cert, &variant).unwrap(), None);
+    }
+
+    #[test]
+    fn test_failing_pre_exec() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_path_buf();
+        let config = Config {
+            certs: vec![],
+            acme: AcmeConfig::default(),
+            system: SystemConfig {
+                data_dir: dir.clone(),
+                on_failure: vec![
+                    format!(r#"echo "$ACME_ERROR" > "{}/failed""#, dir.display()).into(),
+                ],
+                post_exec: vec![format!("touch {}/post", dir.display()).into()],
+                ..Default::default()
+            },
+        };
+        let persist = FilePersist::new(&config);
+        let cert = CertConfig {
+            pre_exec: vec!["false".into()],
+            ..cert_config()
+        };
+        let args = RenewArgs::parse_from(["renew", "--force-renew"]);
+
+        let err = renew_cert(&args, &config, &persist, &cert, None).unwrap_err();
+        assert_eq!(
+            format!("{err:#}"),
+            "Failed to execute pre_exec hooks: Failed to execute hooks: \"false\" (Exited with exit status: 1)"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("failed")).unwrap(),
+            format!("{err:#}\n")
+        );
+        assert!(dir.join("post").exists());
+    }
+
+    #[test]
+    fn test_hook_options() {
+        let options = HookOptions {
+            timeout: Some(Duration::from_secs(1)),
+            retries: 0,
+            fail_mode: HookFailMode::Fail,
+        };
+        let started = Instant::now();
+        let err = execute_hooks(&["sleep 10".into()], &[], &options, false).unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(
+            err.to_string(),
+            "Failed to execute hooks: \"sleep 10\" (Timed out after 1s)"
+        );
+
+        // children of the hook are killed too
+        let tmp = tempfile::tempdir().unwrap();
+        let pidfile = tmp.path().join("hook.pid");
+        let hook = Hook::from(r#"sleep 30 & echo $! > "$PIDFILE"; wait"#);
+        let env = [("PIDFILE", pidfile.to_string_lossy().into_owned())];
+        execute_hooks(&[hook], &env, &options, false).unwrap_err();
+        let pid = fs::read_to_string(&pidfile).unwrap();
+        let stat = PathBuf::from(format!("/proc/{}/stat", pid.trim()));
+        let started = Instant::now();
+        // the orphaned child may be a zombie until it's reaped
+        while fs::read_to_string(&stat).is_ok_and(|stat| !stat.contains(") Z ")) {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            thread::sleep(HOOK_POLL_INTERVAL);
+        }
+
+        // the hook fails on the first attempt and succeeds on the retry
+        let marker = tmp.path().join("marker");
+        let hook = Hook::from(r#"test -e "$MARKER" || { touch "$MARKER"; exit 1; }"#);
+        let env = [("MARKER", marker.to_string_lossy().into_owned())];
+        let options = HookOptions {
+            timeout: None,
+            retries: 1,
+            fail_mode: HookFailMode::Fail,
+        };
+        execute_hooks(&[hook], &env, &options, false).unwrap();
+
+        let options = HookOptions {
+            timeout: None,
+            retries: 0,
+            fail_mode: HookFailMode::Warn,
+        };
+        execute_hooks(&["false".into()], &[], &options, false).unwrap();
+        let options = HookOptions {
+            fail_mode: HookFailMode::Fail,
+            ..options
+        };
+        execute_hooks(&["false".into()], &[], &options, false).unwrap_err();
+    }
+
+    #[test]
+    fn test_argv_hook() {
+        let options = SystemConfig::default().hook_options();
+        // arguments are passed as-is instead of being split by a shell
+        let hook = Hook::from(HookCmd::Argv(vec![
+            "test".to_string(),
+            "a b".to_string(),
+            "=".to_string(),
+            "a b".to_string(),
+        ]));
+        execute_hooks(&[hook], &[], &options, false).unwrap();
+
+        let hook = Hook {
+            cmd: HookCmd::Argv(vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                r#"test "$PWD" = / && test "$FOO" = bar && test "$ACME_CERT_NAME" = example.com"#
+                    .to_string(),
+            ]),
+            user: None,
+            working_dir: Some(PathBuf::from("/")),
+            env: [("FOO".to_string(), "bar".to_string())].into(),
+        };
+        let env = [("ACME_CERT_NAME", "example.com".to_string())];
+        execute_hooks(&[hook], &env, &options, false).unwrap();
+
+        let hook = Hook::from(HookCmd::Argv(vec![]));
+        let err = execute_hooks(&[hook], &[], &options, false).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Failed to execute hooks: [] (Hook command must not be empty)"
+        );
+    }
+
+    #[test]
+    fn test_deferred_hooks() {
+        let options = HookOptions {
+            timeout: None,
+            retries: 0,
+            fail_mode: HookFailMode::Fail,
+        };
+        let lenient = HookOptions {
+            timeout: Some(Duration::from_secs(5)),
+            retries: 2,
+            fail_mode: HookFailMode::Warn,
+        };
+        let reload = Hook::from(r#"test "$ACME_CERT_NAMES" = "a c" -a -z "$ACME_CERT_NAME""#);
+        let single = Hook::from(r#"test "$ACME_CERT_NAME" = b -a "$ACME_CERT_DIRS" = /b"#);
+        let mut deferred = DeferredHooks::default();
+        deferred.add(std::slice::from_ref(&reload), lenient, 0);
+        deferred.add(&["false".into(), single.clone()], options, 1);
+        deferred.add(&[reload.clone(), reload.clone()], options, 2);
+        for (idx, name) in ["a", "b", "c"].into_iter().enumerate() {
+            deferred.add_cert(
+                idx,
+                vec![
+                    ("ACME_CERT_NAME", name.to_string()),
+                    ("ACME_CERT_DIR", format!("/{name}")),
+                ],
+            );
+        }
+        assert_eq!(
+            deferred.hooks,
+            [
+                DeferredHook {
+                    hook: reload,
+                    options: HookOptions {
+                        timeout: None,
+                        retries: 2,
+                        fail_mode: HookFailMode::Fail,
+                    },
+                    certs: vec![0, 2],
+                },
+                DeferredHook {
+                    hook: "false".into(),
+                    options,
+                    certs: vec![1],
+                },
+                DeferredHook {
+                    hook: single,
+                    options,
+                    certs: vec![1],
+                },
+            ]
+        );
+
+        let args = RenewArgs::parse_from(["renew"]);
+        assert_eq!(
+            deferred.hook_env(&args, &deferred.hooks[2]),
+            [
+                ("ACME_CERT_NAME", "b".to_string()),
+                ("ACME_CERT_DIR", "/b".to_string()),
+                ("ACME_CERT_NAMES", "b".to_string()),
+                ("ACME_CERT_DIRS", "/b".to_string()),
+                ("ACME_LIVE_DIRS", String::new()),
+            ]
+        );
+
+        let mut certs = ["a", "b", "c"]
+            .into_iter()
+            .map(|name| CertReport {
+                name: name.to_string(),
+                outcome: Outcome::Renewed,
+                error: None,
+            })
+            .collect::<Vec<_>>();
+        deferred.run(&args, &mut certs);
+        let outcomes = certs.iter().map(|cert| cert.outcome).collect::<Vec<_>>();
+        assert_eq!(
+            outcomes,
+            [Outcome::Renewed, Outcome::HookFailed, Outcome::Renewed]
+        );
+        assert!(certs[1].error.as_ref().unwrap().contains("\"false\""));
+    }
+
+    #[test]
+    fn test_deferred_extra_hooks() {
+        let options = HookOptions {
+            timeout: None,
+            retries: 0,
+            fail_mode: HookFailMode::Fail,
+        };
+        let extra = Hook::from("systemctl reload dovecot");
+        let exec = Hook::from("systemctl reload nginx");
+        let mut deferred = DeferredHooks::default();
+        deferred.add_extra(std::slice::from_ref(&extra), options, 0);
+        deferred.add(std::slice::from_ref(&exec), options, 1);
+        deferred.add_extra(std::slice::from_ref(&extra), options, 1);
+        // the exec hooks of all certificates run before any exec_extra hook
+        assert_eq!(
+            deferred
+                .hooks
+                .iter()
+                .chain(&deferred.extra)
+                .map(|deferred| &deferred.hook)
+                .collect::<Vec<_>>(),
+            [&exec, &extra]
+        );
+
+        // a hook of both is only executed once, with the exec hooks
+        deferred.add(std::slice::from_ref(&extra), options, 2);
+        assert!(deferred.extra.is_empty());
+        assert_eq!(
+            deferred.hooks[1],
+            DeferredHook {
+                hook: extra,
+                options,
+                certs: vec![0, 1, 2],
+            }
+        );
+    }
+
+    #[test]
+    fn test_report() {
+        colored::control::set_override(false);
+        let report = Report {
+            finished: "2026-05-15T16:20:00Z".to_string(),
+            dry_run: false,
+            certs: vec![
+                CertReport {
+                    name: "example.com".to_string(),
+                    outcome: Outcome::Renewed,
+                    error: None,
+                },
+                CertReport {
+                    name: "example.org".to_string(),
+                    outcome: Outcome::HookFailed,
+                    error: Some(
+                        "Failed to execute hooks: \"false\" (Exited with exit status: 1)"
+                            .to_string(),
+                    ),
+                },
+            ],
+        };
+        assert_eq!(report.failed(), 1);
+        assert_eq!(
+            report.summary(),
+            format!(
+                "{:50} renewed\n{:50} hook-failed: Failed to execute hooks: \"false\" (Exited with exit status: 1)\n",
+                "example.com", "example.org"
+            )
+        );
+        assert_eq!(
+            serde_json::to_value(&report).unwrap(),
+            serde_json::json!({
+                "finished": "2026-05-15T16:20:00Z",
+                "dry_run": false,
+                "certs": [
+                    {"name": "example.com", "outcome": "renewed", "error": null},
+                    {"name": "example.org", "outcome": "hook-failed", "error": "Failed to execute hooks: \"false\" (Exited with exit status: 1)"},
+                ],
+            })
+        );
+    }
+}
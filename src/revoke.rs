@@ -0,0 +1,133 @@
+use crate::args::{RenewArgs, RevokeArgs};
+use crate::client::{ApiRevocation, Directory};
+use crate::config::{CertConfig, Config, DEFAULT_ACCOUNT};
+use crate::errors::*;
+use crate::persist::FilePersist;
+use crate::renew;
+use std::fs;
+use std::path::Path;
+
+/// Find the config of a certificate name or versioned folder
+fn find_cert<'a>(config: &'a Config, target: &str) -> Option<&'a CertConfig> {
+    let target = target.strip_prefix("certs/").unwrap_or(target);
+    // versioned folders are named <date>-<name>[-<variant>][-<n>]
+    let name = match target.split_once('-') {
+        Some((date, name)) if date.len() == 8 && date.bytes().all(|b| b.is_ascii_digit()) => name,
+        _ => target,
+    };
+
+    config
+        .certs
+        .iter()
+        .filter(|cert| {
+            name.strip_prefix(cert.name.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with(['/', '-']))
+        })
+        .max_by_key(|cert| cert.name.len())
+}
+
+fn load_cert_der(folder: &Path) -> Result<Vec<u8>> {
+    let path = folder.join("cert");
+    let buf = fs::read(&path).with_context(|| anyhow!("Failed to read {:?}", path))?;
+    let pem = pem::parse(buf).context("Failed to parse pem file")?;
+    Ok(pem.into_contents())
+}
+
+pub fn run(config: Config, args: RevokeArgs) -> Result<()> {
+    let persist = FilePersist::new(&config);
+    persist
+        .migrate_acc_privkey(&config.acme.acme_url)
+        .context("Failed to migrate account key")?;
+    let folders = persist.resolve_cert_folders(&args.cert)?;
+    let cert = find_cert(&config, &args.cert);
+
+    let (acme_url, account) = if let Some(cert) = cert {
+        (cert.acme_url(&config.acme), cert.account())
+    } else {
+        warn!(
+            "No config found for {:?}, using the default acme server",
+            args.cert
+        );
+        (config.acme.acme_url.as_str(), DEFAULT_ACCOUNT)
+    };
+    let reason = args.reason.map(|reason| reason.code());
+
+    let dir = Directory::from_url(acme_url)?;
+    let acc = if args.with_cert_key {
+        None
+    } else {
+        let key = persist
+            .load_acc_privkey(acme_url, account)?
+            .ok_or_else(|| anyhow!("No account key found for {:?}", account))?;
+        Some(dir.find_account(&key)?)
+    };
+
+    for folder in &folders {
+        info!("revoking certificate {:?}", folder);
+        let revocation = ApiRevocation::new(&load_cert_der(folder)?, reason);
+        if let Some(acc) = &acc {
+            acc.revoke(&revocation)?;
+        } else {
+            let path = folder.join("privkey");
+            let key = fs::read_to_string(&path)
+                .with_context(|| anyhow!("Failed to read private key {:?}", path))?;
+            dir.revoke_with_cert_key(&key, &revocation)?;
+        }
+        info!("successfully revoked certificate");
+    }
+
+    if args.reissue {
+        let cert = cert.ok_or_else(|| anyhow!("Can't reissue, no config found"))?;
+        info!("reissuing certificate {:?}", cert.name);
+        let certs = vec![cert.name.clone()];
+        renew::run(
+            config,
+            RenewArgs {
+                dry_run: false,
+                force_renew: true,
+                skip_restarts: false,
+                skip_cleanup: false,
+                hooks_only: false,
+                new_key: true,
+                report: None,
+                certs,
+            },
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AcmeConfig, SystemConfig};
+
+    fn cert_config(name: &str) -> CertConfig {
+        CertConfig {
+            name: name.to_string(),
+            dns_names: vec![name.to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_find_cert() {
+        let config = Config {
+            certs: vec![cert_config("example.com"), cert_config("example.com-foo")],
+            acme: AcmeConfig::default(),
+            system: SystemConfig::default(),
+        };
+
+        let name = |target| find_cert(&config, target).map(|cert| cert.name.as_str());
+        assert_eq!(name("example.com"), Some("example.com"));
+        assert_eq!(name("example.com/rsa"), Some("example.com"));
+        assert_eq!(name("20241004-example.com"), Some("example.com"));
+        assert_eq!(
+            name("certs/20241004-example.com-rsa-1"),
+            Some("example.com")
+        );
+        assert_eq!(name("20241004-example.com-foo"), Some("example.com-foo"));
+        assert_eq!(name("example.org"), None);
+    }
+}
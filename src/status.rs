@@ -1,35 +1,246 @@
-use crate::config::Config;
+use crate::args::{StatusArgs, StatusFormat};
+use crate::cert::CertInfo;
+use crate::config::{ChallengeType, Config};
 use crate::errors::*;
+use crate::nagios;
 use crate::persist::FilePersist;
 use colored::Colorize;
 use nix::unistd::AccessFlags;
-use std::path::Path;
+use serde::Serialize;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::process;
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
 
-pub fn run(config: Config) -> Result<()> {
-    let persist = FilePersist::new(&config);
+#[derive(Debug, Serialize)]
+pub struct CertStatus {
+    pub name: String,
+    pub dns_names: Vec<String>,
+    pub key_type: Option<&'static str>,
+    pub live: Option<LiveStatus>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LiveStatus {
+    pub names: Vec<String>,
+    pub issuer: String,
+    pub serial: String,
+    pub not_before: String,
+    pub not_after: String,
+    pub not_after_timestamp: i64,
+    pub days_left: i64,
+    pub path: Option<PathBuf>,
+}
+
+impl LiveStatus {
+    pub fn new(info: &CertInfo, path: Option<PathBuf>) -> Result<LiveStatus> {
+        Ok(LiveStatus {
+            names: info.names.clone(),
+            issuer: info.issuer.clone(),
+            serial: info.serial.clone(),
+            not_before: info.issued.format(&Rfc3339)?,
+            not_after: info.expires.format(&Rfc3339)?,
+            not_after_timestamp: info.expires.unix_timestamp(),
+            days_left: info.days_left(),
+            path,
+        })
+    }
+}
+
+pub fn collect(config: &Config, persist: &FilePersist) -> Result<Vec<CertStatus>> {
+    let mut status = Vec::new();
+    for cert in &config.certs {
+        for variant in cert.variants(&config.acme) {
+            let key_type = persist
+                .load_key_type(&cert.name, variant.folder)?
+                .map(|key_type| key_type.as_str());
+            let live = if let Some(info) = persist.load_cert_info(&cert.name, variant.folder)? {
+                let path = persist.live_version_path(&cert.name, variant.folder);
+                Some(LiveStatus::new(&info, path)?)
+            } else {
+                None
+            };
+            status.push(CertStatus {
+                name: variant.display_name(&cert.name),
+                dns_names: cert.dns_names.clone(),
+                key_type,
+                live,
+            });
+        }
+    }
+    Ok(status)
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+pub fn prometheus(status: &[CertStatus]) -> String {
+    let mut out = String::new();
+
+    out.push_str("# HELP acme_redirect_cert_live Whether a live certificate exists\n");
+    out.push_str("# TYPE acme_redirect_cert_live gauge\n");
+    for cert in status {
+        writeln!(
+            out,
+            "acme_redirect_cert_live{{name=\"{}\"}} {}",
+            escape_label(&cert.name),
+            cert.live.is_some() as u8
+        )
+        .ok();
+    }
+
+    out.push_str(
+        "# HELP acme_redirect_cert_not_after_timestamp_seconds When the live certificate expires\n",
+    );
+    out.push_str("# TYPE acme_redirect_cert_not_after_timestamp_seconds gauge\n");
+    for cert in status {
+        if let Some(live) = &cert.live {
+            writeln!(
+                out,
+                "acme_redirect_cert_not_after_timestamp_seconds{{name=\"{}\",serial=\"{}\"}} {}",
+                escape_label(&cert.name),
+                escape_label(&live.serial),
+                live.not_after_timestamp
+            )
+            .ok();
+        }
+    }
+
+    out
+}
 
+fn print_text(config: &Config, persist: &FilePersist) -> Result<()> {
+    for cert in &config.certs {
+        let challenge = match cert.challenge() {
+            ChallengeType::Http01 => String::new(),
+            challenge => format!(" ({})", challenge.as_str()),
+        };
+        // TODO: also show alt names?
+        for variant in cert.variants(&config.acme) {
+            let name = variant.display_name(&cert.name);
+            let key_type = persist
+                .load_key_type(&cert.name, variant.folder)?
+                .map(|key_type| format!(" [{}]", key_type.as_str()))
+                .unwrap_or_default();
+            if let Some(info) = persist.load_cert_info(&cert.name, variant.folder)? {
+                let mut mismatch = String::new();
+                if info.must_staple != cert.must_staple {
+                    mismatch += &" (must-staple mismatch)".red().to_string();
+                }
+                let drift = info.name_drift(&cert.names());
+                if !drift.is_empty() {
+                    mismatch += &format!(" (names differ: {drift})").red().to_string();
+                }
+                let now = OffsetDateTime::now_utc();
+                let left = info.expires - now;
+                let status = if left.whole_days() > 0 {
+                    format!("{} days left", left.whole_days())
+                } else {
+                    format!("{} hours left", left.whole_hours())
+                };
+                let status = if !cert.renew_threshold(&config.acme).should_renew(&info, now) {
+                    status.green()
+                } else if left.is_positive() {
+                    status.yellow()
+                } else {
+                    status.red()
+                };
+                println!(
+                    "{:50} {}{}{}{}",
+                    name.bold(),
+                    status,
+                    key_type,
+                    challenge,
+                    mismatch
+                );
+            } else {
+                println!("{:50} -{}", name.bold(), challenge);
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn check_access(config: &Config) -> Result<()> {
     let data_dir = Path::new(&config.system.data_dir);
     nix::unistd::access(data_dir, AccessFlags::X_OK)
         .with_context(|| anyhow!("Detected insufficient permissions to access {:?}", data_dir))?;
+    Ok(())
+}
 
-    for cert in config.certs {
-        let name = cert.name;
-        // TODO: also show alt names?
-        if let Some(cert) = persist.load_cert_info(&name)? {
-            let days_left = cert.days_left();
-            let status = format!("{days_left} days left");
-            let status = if days_left > config.acme.renew_if_days_left {
-                status.green()
-            } else if days_left > 0 {
-                status.yellow()
-            } else {
-                status.red()
-            };
-            println!("{:50} {}", name.bold(), status);
-        } else {
-            println!("{:50} -", name.bold());
+pub fn run(config: Config, args: StatusArgs) -> Result<()> {
+    let persist = FilePersist::new(&config);
+
+    match args.format {
+        StatusFormat::Text => {
+            check_access(&config)?;
+            print_text(&config, &persist)?;
+        }
+        StatusFormat::Json => {
+            check_access(&config)?;
+            let status = collect(&config, &persist)?;
+            let json = serde_json::to_string_pretty(&status)?;
+            println!("{json}");
+        }
+        StatusFormat::Prometheus => {
+            check_access(&config)?;
+            let status = collect(&config, &persist)?;
+            print!("{}", prometheus(&status));
+        }
+        // errors are reported as UNKNOWN instead of being returned
+        StatusFormat::Nagios => {
+            let state = nagios::run(&config, &args);
+            process::exit(state.code());
         }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prometheus() {
+        let status = vec![
+            CertStatus {
+                name: "example.com".to_string(),
+                dns_names: vec!["example.com".to_string()],
+                key_type: Some("ecdsa-p384"),
+                live: Some(LiveStatus {
+                    names: vec!["example.com".to_string()],
+                    issuer: "C=US, O=Let's Encrypt, CN=E7".to_string(),
+                    serial: "05:18".to_string(),
+                    not_before: "2026-05-07T16:14:37Z".to_string(),
+                    not_after: "2026-08-05T16:14:36Z".to_string(),
+                    not_after_timestamp: 1785946476,
+                    days_left: 82,
+                    path: None,
+                }),
+            },
+            CertStatus {
+                name: "new\"cert".to_string(),
+                dns_names: vec![],
+                key_type: None,
+                live: None,
+            },
+        ];
+        assert_eq!(
+            prometheus(&status),
+            r#"# HELP acme_redirect_cert_live Whether a live certificate exists
+# TYPE acme_redirect_cert_live gauge
+acme_redirect_cert_live{name="example.com"} 1
+acme_redirect_cert_live{name="new\"cert"} 0
+# HELP acme_redirect_cert_not_after_timestamp_seconds When the live certificate expires
+# TYPE acme_redirect_cert_not_after_timestamp_seconds gauge
+acme_redirect_cert_not_after_timestamp_seconds{name="example.com",serial="05:18"} 1785946476
+"#
+        );
+    }
+}
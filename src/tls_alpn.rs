@@ -0,0 +1,304 @@
+use crate::chall;
+use crate::errors::*;
+use openssl::asn1::{Asn1Object, Asn1OctetString, Asn1Time};
+use openssl::bn::{BigNum, MsbOption};
+use openssl::ec::{EcGroup, EcKey};
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use openssl::ssl::{AlpnError, NameType, SniError, SslAcceptor, SslAlert, SslMethod, SslRef};
+use openssl::x509::extension::SubjectAlternativeName;
+use openssl::x509::{X509, X509Extension, X509NameBuilder};
+use std::fs;
+use std::net::{IpAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+// ALPN wire format, prefixed with the length of the protocol name
+const ACME_TLS_ALPN: &[u8] = b"\x0aacme-tls/1";
+// id-pe-acmeIdentifier, https://www.rfc-editor.org/rfc/rfc8737#section-6.1
+const ACME_IDENTIFIER_OID: &str = "1.3.6.1.5.5.7.1.31";
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_CONNECTIONS: usize = 64;
+
+pub fn decode_proof(hex: &str) -> Result<[u8; 32]> {
+    let hex = hex.trim();
+    if hex.len() != 64 || !hex.is_ascii() {
+        bail!("Proof has invalid length");
+    }
+
+    let mut proof = [0; 32];
+    for (i, b) in proof.iter_mut().enumerate() {
+        *b = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).context("Proof is not valid hex")?;
+    }
+    Ok(proof)
+}
+
+/// The server name that is used for ip identifiers, see https://www.rfc-editor.org/rfc/rfc8738#section-6
+pub fn reverse_name(addr: &IpAddr) -> String {
+    match addr {
+        IpAddr::V4(addr) => {
+            let o = addr.octets();
+            format!("{}.{}.{}.{}.in-addr.arpa", o[3], o[2], o[1], o[0])
+        }
+        IpAddr::V6(addr) => {
+            let mut name = String::new();
+            for b in addr.octets().iter().rev() {
+                name.push_str(&format!("{:x}.{:x}.", b & 0xf, b >> 4));
+            }
+            name.push_str("ip6.arpa");
+            name
+        }
+    }
+}
+
+pub fn parse_reverse_name(name: &str) -> Option<IpAddr> {
+    if let Some(name) = name.strip_suffix(".in-addr.arpa") {
+        let mut octets = name
+            .split('.')
+            .map(|o| o.parse::<u8>().ok())
+            .collect::<Option<Vec<_>>>()?;
+        octets.reverse();
+        let octets: [u8; 4] = octets.try_into().ok()?;
+        Some(IpAddr::from(octets))
+    } else if let Some(name) = name.strip_suffix(".ip6.arpa") {
+        let nibbles = name
+            .split('.')
+            .map(|n| match n.len() {
+                1 => u8::from_str_radix(n, 16).ok(),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+        if nibbles.len() != 32 {
+            return None;
+        }
+        let mut octets = [0; 16];
+        for (i, pair) in nibbles.chunks(2).rev().enumerate() {
+            octets[i] = (pair[1] << 4) | pair[0];
+        }
+        Some(IpAddr::from(octets))
+    } else {
+        None
+    }
+}
+
+pub fn validation_cert(domain: &str, proof: &[u8; 32]) -> Result<(X509, PKey<Private>)> {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+    let pkey = PKey::from_ec_key(EcKey::generate(&group)?)?;
+    let addr = parse_reverse_name(domain);
+
+    let mut name = X509NameBuilder::new()?;
+    match addr {
+        Some(addr) => name.append_entry_by_nid(Nid::COMMONNAME, &addr.to_string())?,
+        None => name.append_entry_by_nid(Nid::COMMONNAME, domain)?,
+    }
+    let name = name.build();
+
+    let mut serial = BigNum::new()?;
+    serial.rand(128, MsbOption::MAYBE_ZERO, false)?;
+    let serial = serial.to_asn1_integer()?;
+    let not_before = Asn1Time::days_from_now(0)?;
+    let not_after = Asn1Time::days_from_now(1)?;
+
+    let mut builder = X509::builder()?;
+    builder.set_version(2)?;
+    builder.set_serial_number(&serial)?;
+    builder.set_subject_name(&name)?;
+    builder.set_issuer_name(&name)?;
+    builder.set_pubkey(&pkey)?;
+    builder.set_not_before(&not_before)?;
+    builder.set_not_after(&not_after)?;
+
+    let mut san = SubjectAlternativeName::new();
+    match addr {
+        Some(addr) => san.ip(&addr.to_string()),
+        None => san.dns(domain),
+    };
+    let san = san.build(&builder.x509v3_context(None, None))?;
+    builder.append_extension(san)?;
+
+    // The extension value is the DER encoded octet string of the proof
+    let mut value = vec![0x04, 0x20];
+    value.extend_from_slice(proof);
+    let oid = Asn1Object::from_str(ACME_IDENTIFIER_OID)?;
+    let value = Asn1OctetString::new_from_bytes(&value)?;
+    builder.append_extension(X509Extension::new_from_der(&oid, true, &value)?)?;
+
+    builder.sign(&pkey, MessageDigest::sha256())?;
+    Ok((builder.build(), pkey))
+}
+
+fn setup_validation_cert(ssl: &mut SslRef) -> Result<()> {
+    let domain = ssl
+        .servername(NameType::HOST_NAME)
+        .ok_or_else(|| anyhow!("Client didn't send a server name"))?
+        .to_ascii_lowercase();
+    if !chall::valid_domain(&domain) {
+        bail!("Client sent invalid server name");
+    }
+
+    let path = Path::new("tls-alpn").join(&domain);
+    debug!("Reading tls-alpn challenge proof: {:?}", path);
+    let proof = fs::read_to_string(&path)
+        .with_context(|| anyhow!("No tls-alpn challenge for {:?}", domain))?;
+    let proof = decode_proof(&proof)?;
+
+    let (cert, pkey) = validation_cert(&domain, &proof)?;
+    ssl.set_certificate(&cert)?;
+    ssl.set_private_key(&pkey)?;
+    Ok(())
+}
+
+fn acceptor() -> Result<SslAcceptor> {
+    let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls())?;
+    builder.set_alpn_select_callback(|_, client| {
+        openssl::ssl::select_next_proto(ACME_TLS_ALPN, client).ok_or(AlpnError::ALERT_FATAL)
+    });
+    builder.set_servername_callback(|ssl, alert| {
+        if let Err(err) = setup_validation_cert(ssl) {
+            info!("tls-alpn: {:#}", err);
+            *alert = SslAlert::UNRECOGNIZED_NAME;
+            return Err(SniError::ALERT_FATAL);
+        }
+        Ok(())
+    });
+    Ok(builder.build())
+}
+
+/// Counts a running handshake until it's dropped
+struct ConnectionSlot(Arc<AtomicUsize>);
+
+impl ConnectionSlot {
+    fn acquire(active: &Arc<AtomicUsize>) -> Option<ConnectionSlot> {
+        let previous = active.fetch_add(1, Ordering::SeqCst);
+        let slot = ConnectionSlot(active.clone());
+        if previous < MAX_CONNECTIONS {
+            Some(slot)
+        } else {
+            None
+        }
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn handle(acceptor: &SslAcceptor, stream: TcpStream) -> Result<()> {
+    stream.set_read_timeout(Some(CONNECTION_TIMEOUT))?;
+    stream.set_write_timeout(Some(CONNECTION_TIMEOUT))?;
+
+    let mut stream = acceptor
+        .accept(stream)
+        .map_err(|err| anyhow!("Handshake failed: {}", err))?;
+    info!(
+        "tls-alpn: answered challenge for {:?}",
+        stream.ssl().servername(NameType::HOST_NAME)
+    );
+    stream.shutdown().ok();
+    Ok(())
+}
+
+pub fn spawn(socket: TcpListener) -> Result<()> {
+    let acceptor = Arc::new(acceptor().context("Failed to setup tls acceptor")?);
+    let active = Arc::new(AtomicUsize::new(0));
+
+    thread::spawn(move || {
+        for stream in socket.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    error!("Failed to accept tls connection: {:#}", err);
+                    continue;
+                }
+            };
+
+            let Some(slot) = ConnectionSlot::acquire(&active) else {
+                debug!("tls-alpn: too many connections, dropping connection");
+                continue;
+            };
+            let acceptor = acceptor.clone();
+            thread::spawn(move || {
+                let _slot = slot;
+                if let Err(err) = handle(&acceptor, stream) {
+                    debug!("tls-alpn: {:#}", err);
+                }
+            });
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_proof() {
+        let proof =
+            decode_proof("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f\n")
+                .unwrap();
+        assert_eq!(
+            proof,
+            [
+                0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22,
+                23, 24, 25, 26, 27, 28, 29, 30, 31
+            ]
+        );
+        assert!(decode_proof("0001").is_err());
+        assert!(decode_proof(&"zz".repeat(32)).is_err());
+    }
+
+    #[test]
+    fn test_connection_slot() {
+        let active = Arc::new(AtomicUsize::new(0));
+        let mut slots = (0..MAX_CONNECTIONS)
+            .map(|_| ConnectionSlot::acquire(&active).unwrap())
+            .collect::<Vec<_>>();
+        assert!(ConnectionSlot::acquire(&active).is_none());
+        assert_eq!(active.load(Ordering::SeqCst), MAX_CONNECTIONS);
+
+        slots.pop();
+        assert!(ConnectionSlot::acquire(&active).is_some());
+        drop(slots);
+        assert_eq!(active.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_validation_cert() {
+        let (cert, _) = validation_cert("example.com", &[0x42; 32]).unwrap();
+        let der = cert.to_der().unwrap();
+        let (_, cert) = x509_parser::parse_x509_certificate(&der).unwrap();
+
+        let ext = cert
+            .extensions()
+            .iter()
+            .find(|ext| ext.oid.to_id_string() == ACME_IDENTIFIER_OID)
+            .unwrap();
+        assert!(ext.critical);
+        assert_eq!(&ext.value[..2], &[0x04, 0x20]);
+        assert_eq!(&ext.value[2..], &[0x42; 32]);
+    }
+
+    #[test]
+    fn test_reverse_name() {
+        let addr = "192.0.2.1".parse().unwrap();
+        assert_eq!(reverse_name(&addr), "1.2.0.192.in-addr.arpa");
+        assert_eq!(parse_reverse_name("1.2.0.192.in-addr.arpa"), Some(addr));
+
+        let addr = "2001:db8::1".parse().unwrap();
+        let name = "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa";
+        assert_eq!(reverse_name(&addr), name);
+        assert_eq!(parse_reverse_name(name), Some(addr));
+
+        assert_eq!(parse_reverse_name("example.com"), None);
+        assert_eq!(parse_reverse_name("1.2.0.256.in-addr.arpa"), None);
+        assert_eq!(parse_reverse_name("1.2.ip6.arpa"), None);
+    }
+}